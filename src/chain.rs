@@ -0,0 +1,117 @@
+//! Markov chains whose generation is driven by an RNG passed in by the
+//! caller, so it can be seeded.
+//!
+//! Chains serialize the same way as the ones of the `markov` crate that were
+//! used before, so existing saves keep loading.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// `None` marks the start or the end of a sequence.
+type Token<T> = Option<T>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "T: Serialize",
+    deserialize = "T: Deserialize<'de> + Ord + Hash"
+))]
+pub struct Chain<T: Ord + Hash> {
+    /// Every state, to the tokens that followed it and how often they did.
+    /// The tokens are kept sorted so that picking one only depends on the RNG.
+    map: HashMap<Vec<Token<T>>, BTreeMap<Token<T>, usize>>,
+    order: usize,
+}
+
+impl<T: Ord + Hash + Clone> Default for Chain<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord + Hash + Clone> Chain<T> {
+    pub fn new() -> Self {
+        Self::of_order(1)
+    }
+
+    /// A chain where every state is made of the last `order` tokens.
+    pub fn of_order(order: usize) -> Self {
+        assert!(order != 0, "chain order can't be zero");
+        let mut map = HashMap::new();
+        map.insert(vec![None; order], BTreeMap::new());
+        Self { map, order }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self.map.get(&vec![None; self.order]) {
+            Some(starts) => starts.is_empty(),
+            None => true,
+        }
+    }
+
+    pub fn feed<S: AsRef<[T]>>(&mut self, tokens: S) -> &mut Self {
+        let tokens = tokens.as_ref();
+        if tokens.is_empty() {
+            return self;
+        }
+        let mut sequence = vec![None; self.order];
+        sequence.extend(tokens.iter().cloned().map(Some));
+        sequence.push(None);
+        for window in sequence.windows(self.order + 1) {
+            let (state, next) = window.split_at(self.order);
+            *self
+                .map
+                .entry(state.to_vec())
+                .or_default()
+                .entry(next[0].clone())
+                .or_insert(0) += 1;
+        }
+        self
+    }
+
+    /// Generates a sequence from the start.
+    pub fn generate(&self, rng: &mut impl Rng) -> Vec<T> {
+        self.walk(vec![None; self.order], Vec::new(), rng)
+    }
+
+    /// Generates a sequence starting with `token`, or nothing if it was never
+    /// fed at the start of a state.
+    pub fn generate_from_token(&self, token: T, rng: &mut impl Rng) -> Vec<T> {
+        let mut state = vec![None; self.order - 1];
+        state.push(Some(token.clone()));
+        if !self.map.contains_key(&state) {
+            return Vec::new();
+        }
+        self.walk(state, vec![token], rng)
+    }
+
+    fn walk(&self, mut state: Vec<Token<T>>, mut output: Vec<T>, rng: &mut impl Rng) -> Vec<T> {
+        while let Some(token) = self.map.get(&state).and_then(|next| pick(next, rng)) {
+            state.remove(0);
+            state.push(Some(token.clone()));
+            output.push(token);
+        }
+        output
+    }
+}
+
+/// Picks one of the tokens weighted by how often it was seen, `None` if the
+/// sequence ends here.
+fn pick<T: Clone>(next: &BTreeMap<Token<T>, usize>, rng: &mut impl Rng) -> Option<T> {
+    let total = next.values().sum::<usize>();
+    if total == 0 {
+        return None;
+    }
+    let mut n = rng.gen_range(0..total);
+    for (token, count) in next {
+        if n < *count {
+            return token.clone();
+        }
+        n -= count;
+    }
+    unreachable!("`n` is less than the sum of the counts")
+}
@@ -0,0 +1,226 @@
+use std::{
+    convert::Infallible,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{perms::Level, runtime::Shutdown, BotError, Handler};
+
+use super::{perr, Bot};
+use smol_str::SmolStr;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const BOT_ID: &str = "bernbot";
+
+const CLI_HELP_TEXT: &str = "anything not starting with `:` is sent as a message.
+session commands are:
+- `:author <id>`: set the author of the next messages
+- `:channel <id>`: set the channel messages are sent in
+- `:guild [id]`: set the guild, no argument means direct messages
+- `:level <level>`: set the level the author has in the guild (`user`, `moderator`, `admin` or `owner`)
+- `:role <id>`: give the author a role, or take it away if they have it
+- `:bot`: toggle whether the author is a bot
+- `:reply <id>`: make the next message a reply to message `id`
+- `:status`: show the current session
+- `:quit`: end the terminal session";
+
+/// The fake identity messages typed into the terminal are sent with.
+struct Session {
+    author: SmolStr,
+    channel_id: SmolStr,
+    guild_id: Option<SmolStr>,
+    level: Level,
+    roles: Vec<SmolStr>,
+    is_bot: bool,
+    referenced_id: Option<SmolStr>,
+}
+
+impl Session {
+    fn from_env() -> Self {
+        let var = |name: &str, def: &str| -> SmolStr {
+            std::env::var(name).map_or_else(|_| def.into(), Into::into)
+        };
+        Self {
+            author: var("CLI_AUTHOR", "user"),
+            channel_id: var("CLI_CHANNEL", "cli"),
+            guild_id: Some(var("CLI_GUILD", "cli")).filter(|id| !id.is_empty()),
+            level: match std::env::var("CLI_ADMIN") {
+                Ok(v) if v == "0" => Level::User,
+                _ => Level::Admin,
+            },
+            roles: Vec::new(),
+            is_bot: false,
+            referenced_id: None,
+        }
+    }
+
+    fn status(&self) -> String {
+        format!(
+            "author `{}`, channel `{}`, guild `{}`, level `{}`, roles `{}`, bot `{}`",
+            self.author,
+            self.channel_id,
+            self.guild_id.as_deref().unwrap_or("none"),
+            self.level,
+            self.roles.join(","),
+            self.is_bot,
+        )
+    }
+
+    /// Runs a session command, returns `false` if the session should end.
+    fn run_command(&mut self, line: &str) -> bool {
+        let mut args = line.split_whitespace();
+        match args.next().unwrap_or_default() {
+            "author" => match args.next() {
+                Some(id) => self.author = id.into(),
+                None => println!("need an author id"),
+            },
+            "channel" => match args.next() {
+                Some(id) => self.channel_id = id.into(),
+                None => println!("need a channel id"),
+            },
+            "guild" => self.guild_id = args.next().map(Into::into),
+            "level" => match args.next().map(str::parse) {
+                Some(Ok(level)) => self.level = level,
+                _ => println!("need one of `user`, `moderator`, `admin` and `owner`"),
+            },
+            "role" => match args.next() {
+                Some(id) => match self.roles.iter().position(|role| role == id) {
+                    Some(i) => {
+                        self.roles.remove(i);
+                    }
+                    None => self.roles.push(id.into()),
+                },
+                None => println!("need a role id"),
+            },
+            "bot" => self.is_bot = !self.is_bot,
+            "reply" => self.referenced_id = args.next().map(Into::into),
+            "status" => println!("{}", self.status()),
+            "quit" | "q" => return false,
+            _ => println!("{}", CLI_HELP_TEXT),
+        }
+        true
+    }
+}
+
+struct CliHandler<'a> {
+    session: &'a Session,
+    next_id: &'a AtomicU64,
+    id: SmolStr,
+    content: &'a str,
+}
+
+#[async_trait::async_trait]
+impl<'a> Handler for CliHandler<'a> {
+    type Error = Infallible;
+
+    async fn author_level(&self) -> Result<Level, BotError<Self::Error>> {
+        Ok(self.session.level)
+    }
+
+    async fn author_roles(&self) -> Result<Vec<SmolStr>, BotError<Self::Error>> {
+        Ok(self.session.roles.clone())
+    }
+
+    async fn send_message(
+        &self,
+        text: &str,
+        attach: Option<(&str, Vec<u8>)>,
+        reply: bool,
+    ) -> Result<SmolStr, BotError<Self::Error>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if reply {
+            println!("[{}] (reply to {}) {}", id, self.id, text);
+        } else {
+            println!("[{}] {}", id, text);
+        }
+        if let Some((name, data)) = attach {
+            println!("<attachment `{}`, {} bytes>", name, data.len());
+        }
+        Ok(id.to_string().into())
+    }
+
+    async fn leave_guild(&self, guild_id: &str) -> Result<bool, BotError<Self::Error>> {
+        println!("<left guild `{}`>", guild_id);
+        Ok(true)
+    }
+
+    async fn send_to(&self, channel_id: &str, text: &str) -> Result<bool, BotError<Self::Error>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        println!("[{}] (in {}) {}", id, channel_id, text);
+        Ok(true)
+    }
+
+    fn platform(&self) -> &str {
+        "cli"
+    }
+
+    fn bot_user_id(&self) -> &str {
+        BOT_ID
+    }
+
+    fn author_is_bot(&self) -> bool {
+        self.session.is_bot
+    }
+
+    fn referenced_id(&self) -> Option<&str> {
+        self.session.referenced_id.as_deref()
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn author(&self) -> &str {
+        &self.session.author
+    }
+
+    fn content(&self) -> &str {
+        self.content
+    }
+
+    fn channel_id(&self) -> &str {
+        &self.session.channel_id
+    }
+
+    fn guild_id(&self) -> Option<&str> {
+        self.session.guild_id.as_deref()
+    }
+}
+
+pub async fn run(bot: Bot, mut shutdown: Shutdown) {
+    let mut session = Session::from_env();
+    let next_id = AtomicU64::new(0);
+    println!("{}\ntype `:help` for session commands", session.status());
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => line,
+            _ = shutdown.wait() => break,
+        };
+        let line = match line {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                tracing::error!("couldnt read stdin: {}", err);
+                break;
+            }
+        };
+
+        if let Some(command) = line.strip_prefix(':') {
+            if session.run_command(command) {
+                continue;
+            }
+            break;
+        }
+
+        let id = next_id.fetch_add(1, Ordering::Relaxed);
+        let handler = CliHandler {
+            session: &session,
+            next_id: &next_id,
+            id: id.to_string().into(),
+            content: &line,
+        };
+        perr!(bot.process_args(&handler).await);
+        session.referenced_id = None;
+    }
+}
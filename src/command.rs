@@ -0,0 +1,350 @@
+//! Commands the bot responds to.
+//!
+//! Every command implements [`Command`], declaring its name, aliases, help
+//! text, who can run it and the arguments it takes, and is kept in a
+//! [`Registry`]. The registry finds the command a message is for, checks
+//! permissions and renders help from those declarations, so a command only
+//! has to do its own thing.
+
+use std::fmt::Write;
+
+use async_trait::async_trait;
+use smol_str::SmolStr;
+
+use crate::{perms::Level, Bot, BotError};
+
+mod fuckyou;
+mod gen;
+mod help;
+mod listen;
+mod owner;
+mod perms;
+mod poem;
+mod privacy;
+mod set;
+
+/// Who can run a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Everyone,
+    /// Only users of at least the level.
+    Level(Level),
+    /// Everyone without arguments (to look at a setting), only users of at
+    /// least the level with them (to change it).
+    LevelToChange(Level),
+    /// Only the users running the bot, which servers can't change.
+    BotOwner,
+}
+
+impl Permission {
+    /// The level running it needs, given whether arguments were passed.
+    pub fn level(self, has_args: bool) -> Level {
+        match self {
+            Permission::Everyone => Level::User,
+            Permission::Level(level) => level,
+            Permission::LevelToChange(level) if has_args => level,
+            Permission::LevelToChange(_) => Level::User,
+            Permission::BotOwner => Level::Owner,
+        }
+    }
+
+    /// The same permission, needing `level` instead.
+    pub fn with_level(self, level: Level) -> Self {
+        match self {
+            Permission::Everyone | Permission::Level(_) => Permission::Level(level),
+            Permission::LevelToChange(_) => Permission::LevelToChange(level),
+            Permission::BotOwner => Permission::BotOwner,
+        }
+    }
+
+    fn note(self) -> String {
+        match self {
+            Permission::Everyone | Permission::Level(Level::User) => String::new(),
+            Permission::Level(level) => format!(" (needs {})", level),
+            Permission::LevelToChange(level) => format!(" (changing it needs {})", level),
+            Permission::BotOwner => " (bot owners only)".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    Text,
+    Integer,
+    Number,
+    User,
+    Channel,
+    Role,
+    /// Everything left in the message.
+    Rest,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub required: bool,
+    pub description: &'static str,
+}
+
+impl Arg {
+    pub const fn required(name: &'static str, kind: ArgKind, description: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            required: true,
+            description,
+        }
+    }
+
+    pub const fn optional(name: &'static str, kind: ArgKind, description: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            required: false,
+            description,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Subcommand {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub permission: Permission,
+    pub args: &'static [Arg],
+}
+
+/// What a command answers with.
+#[derive(Debug)]
+pub enum Reply {
+    Text(SmolStr),
+    Attachment {
+        name: &'static str,
+        data: &'static [u8],
+    },
+    /// The (sub)command doesn't exist, which gets the author insulted.
+    Unrecognised(SmolStr),
+    /// Leave the server with this id on the platform.
+    LeaveGuild(SmolStr),
+    /// Send the text to every channel the bot speaks in on the platform.
+    Broadcast(SmolStr),
+}
+
+impl From<SmolStr> for Reply {
+    fn from(text: SmolStr) -> Self {
+        Reply::Text(text)
+    }
+}
+
+/// Where a command was sent and what was passed to it. Ids are namespaced.
+#[derive(Debug)]
+pub struct Context<'a> {
+    pub bot: &'a Bot,
+    pub platform: &'a str,
+    /// Id of the author on the platform, not namespaced.
+    pub author: &'a str,
+    pub channel_id: &'a str,
+    pub guild_id: Option<&'a str>,
+    /// What per server settings are kept under, the channel outside servers.
+    pub context_id: &'a str,
+    /// Arguments after the command name.
+    pub args: &'a [&'a str],
+}
+
+#[async_trait]
+pub trait Command: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// A line on what it does, shown in the command list.
+    fn description(&self) -> &'static str;
+
+    /// More about it, shown by `help <command>` above the generated usage.
+    fn help(&self) -> &'static str {
+        self.description()
+    }
+
+    /// Who can run it when no subcommand is given.
+    fn permission(&self) -> Permission {
+        Permission::Everyone
+    }
+
+    /// Arguments it takes when no subcommand is given.
+    fn args(&self) -> &'static [Arg] {
+        &[]
+    }
+
+    fn subcommands(&self) -> &'static [Subcommand] {
+        &[]
+    }
+
+    /// What running it without a subcommand is called where one has to be
+    /// picked, like Discord slash commands. `None` leaves it out there.
+    fn bare_name(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Whether running it with `args` can take a while, like when it reads
+    /// the corpus.
+    fn is_slow(&self, _args: &[&str]) -> bool {
+        false
+    }
+
+    /// Errors are told to the author by [`Bot::run_command`].
+    async fn run(&self, ctx: &Context<'_>) -> Result<Reply, BotError>;
+}
+
+impl<'a> dyn Command + 'a {
+    pub fn subcommand(&self, name: &str) -> Option<&'static Subcommand> {
+        self.subcommands().iter().find(|sub| sub.name == name)
+    }
+
+    /// Who can run it with `args`.
+    pub fn permission_for(&self, args: &[&str]) -> (Permission, bool) {
+        match args.split_first() {
+            Some((name, rest)) => match self.subcommand(name) {
+                Some(sub) => (sub.permission, !rest.is_empty()),
+                None => (self.permission(), true),
+            },
+            None => (self.permission(), false),
+        }
+    }
+
+    /// Help text with the usage, arguments and subcommands filled in.
+    pub fn render_help(&self) -> String {
+        let mut text = self.help().to_owned();
+        if !self.aliases().is_empty() {
+            let aliases = self
+                .aliases()
+                .iter()
+                .map(|alias| format!("`{}`", alias))
+                .collect::<Vec<_>>();
+            let _ = write!(text, "\n\nalso called {}", aliases.join(", "));
+        }
+        let _ = write!(
+            text,
+            "\n\nusage: `{}`{}",
+            usage(self.name(), self.args()),
+            self.permission().note()
+        );
+        write_args(&mut text, self.args());
+        if !self.subcommands().is_empty() {
+            text.push_str("\n\nsubcommands are:");
+            for sub in self.subcommands() {
+                let _ = write!(
+                    text,
+                    "\n- `{}`: {}{}",
+                    usage(sub.name, sub.args),
+                    sub.description,
+                    sub.permission.note()
+                );
+                write_args(&mut text, sub.args);
+            }
+        }
+        text
+    }
+}
+
+fn usage(name: &str, args: &[Arg]) -> String {
+    let mut usage = name.to_owned();
+    for arg in args {
+        let dots = if arg.kind == ArgKind::Rest { "..." } else { "" };
+        if arg.required {
+            let _ = write!(usage, " <{}{}>", arg.name, dots);
+        } else {
+            let _ = write!(usage, " [{}{}]", arg.name, dots);
+        }
+    }
+    usage
+}
+
+fn write_args(text: &mut String, args: &[Arg]) {
+    for arg in args.iter().filter(|arg| !arg.description.is_empty()) {
+        let _ = write!(text, "\n  - `{}`: {}", arg.name, arg.description);
+    }
+}
+
+/// Every command the bot knows.
+pub struct Registry {
+    commands: Vec<Box<dyn Command>>,
+}
+
+impl Registry {
+    pub fn empty() -> Self {
+        Self {
+            commands: Vec::new(),
+        }
+    }
+
+    /// Adds `command`, panicking if its name or an alias is taken already, or
+    /// if its bare name is one of its subcommands.
+    pub fn register(&mut self, command: impl Command + 'static) {
+        for name in std::iter::once(command.name()).chain(command.aliases().iter().copied()) {
+            assert!(
+                self.get(name).is_none(),
+                "command name `{}` is taken already",
+                name
+            );
+        }
+        if let Some(bare) = command.bare_name() {
+            assert!(
+                (&command as &dyn Command).subcommand(bare).is_none(),
+                "`{}` of `{}` is a subcommand already",
+                bare,
+                command.name()
+            );
+        }
+        self.commands.push(Box::new(command));
+    }
+
+    /// Finds a command by its name or one of its aliases.
+    pub fn get(&self, name: &str) -> Option<&dyn Command> {
+        self.commands
+            .iter()
+            .find(|command| command.name() == name || command.aliases().contains(&name))
+            .map(AsRef::as_ref)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Command> {
+        self.commands.iter().map(AsRef::as_ref)
+    }
+
+    /// The list of commands shown by `help`.
+    pub fn render_help(&self) -> String {
+        let mut text = String::from("commands are:");
+        for command in self.iter() {
+            let _ = write!(text, "\n- `{}`: {}", command.name(), command.description());
+        }
+        text.push_str("\n\nuse `help command` to get more information about a command");
+        text
+    }
+}
+
+impl Default for Registry {
+    /// Every built in command.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(help::Help);
+        registry.register(set::Set);
+        registry.register(poem::Poem);
+        registry.register(gen::Gen);
+        registry.register(listen::Listen);
+        registry.register(privacy::Privacy);
+        registry.register(perms::Perms);
+        registry.register(owner::Owner);
+        registry.register(fuckyou::FuckYou);
+        registry
+    }
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.commands.iter().map(|command| command.name()))
+            .finish()
+    }
+}
@@ -0,0 +1,28 @@
+use async_trait::async_trait;
+
+use super::{Command, Context, Reply};
+use crate::{BotError, UMAD_JPG};
+
+pub struct FuckYou;
+
+#[async_trait]
+impl Command for FuckYou {
+    fn name(&self) -> &'static str {
+        "fuckyou"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["umad"]
+    }
+
+    fn description(&self) -> &'static str {
+        "posts funny \"u mad?\" image"
+    }
+
+    async fn run(&self, _: &Context<'_>) -> Result<Reply, BotError> {
+        Ok(Reply::Attachment {
+            name: "umad.jpg",
+            data: UMAD_JPG,
+        })
+    }
+}
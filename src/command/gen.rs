@@ -0,0 +1,112 @@
+use async_trait::async_trait;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use smol_str::SmolStr;
+
+use super::{Arg, ArgKind, Command, Context, Permission, Reply, Subcommand};
+use crate::BotError;
+
+pub struct Gen;
+
+const NOT_A_SEED: &str = "the seed has to be a number";
+
+const ARGS: &[Arg] = &[Arg::optional("user", ArgKind::User, "")];
+
+const SUBCOMMANDS: &[Subcommand] = &[
+    Subcommand {
+        name: "poem",
+        description: "generates a random poem",
+        permission: Permission::Everyone,
+        args: &[],
+    },
+    Subcommand {
+        name: "token",
+        description: "generate random text starting with a word",
+        permission: Permission::Everyone,
+        args: &[Arg::required("token", ArgKind::Text, "")],
+    },
+    Subcommand {
+        name: "seed",
+        description: "generate with a fixed seed, so the same seed gives the same output as long as the chain doesn't change",
+        permission: Permission::Everyone,
+        args: &[
+            Arg::required("n", ArgKind::Integer, ""),
+            Arg::optional("subcommand", ArgKind::Rest, ""),
+        ],
+    },
+    Subcommand {
+        name: "server",
+        description: "generate random text using the server chain",
+        permission: Permission::Everyone,
+        args: &[],
+    },
+    Subcommand {
+        name: "from",
+        description: "generate random text using the chain of another channel in this server",
+        permission: Permission::Everyone,
+        args: &[Arg::required("channel", ArgKind::Channel, "")],
+    },
+];
+
+#[async_trait]
+impl Command for Gen {
+    fn name(&self) -> &'static str {
+        "gen"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["generate"]
+    }
+
+    fn description(&self) -> &'static str {
+        "generate stuff from markov chains"
+    }
+
+    fn help(&self) -> &'static str {
+        "generate stuff from markov chains
+
+if called with no arguments it will generate random text using the channel's markov chain
+if called with a user id it will generate random text using the user's markov chain in this channel
+if the channel has nothing to generate from, the server chain is used when there is one"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        ARGS
+    }
+
+    fn subcommands(&self) -> &'static [Subcommand] {
+        SUBCOMMANDS
+    }
+
+    fn bare_name(&self) -> Option<&'static str> {
+        Some("text")
+    }
+
+    async fn run(&self, ctx: &Context<'_>) -> Result<Reply, BotError> {
+        let text = match ctx.args {
+            ["seed", seed, rest @ ..] => match seed.parse() {
+                Ok(seed) => generate(ctx, rest, &mut SmallRng::seed_from_u64(seed)),
+                Err(_) => return Err(BotError::InvalidArgument(NOT_A_SEED.into())),
+            },
+            ["seed"] => return Err(BotError::InvalidArgument(NOT_A_SEED.into())),
+            args => generate(ctx, args, &mut *ctx.bot.rng.lock()),
+        };
+        Ok(Reply::Text(text))
+    }
+}
+
+/// Generates everything with `rng`.
+fn generate(ctx: &Context<'_>, args: &[&str], rng: &mut impl Rng) -> SmolStr {
+    let bot = ctx.bot;
+    match args {
+        ["poem", ..] => bot.generate_poem(rng),
+        ["token", token, ..] => {
+            bot.gen_message(ctx.channel_id, ctx.guild_id, Some((*token).into()), rng)
+        }
+        ["token"] => SmolStr::new_inline("put a token"),
+        ["server", ..] => bot.gen_guild_message(ctx.guild_id, rng),
+        ["from", channel, ..] => bot.gen_channel_message(ctx.platform, ctx.guild_id, channel, rng),
+        ["from"] => SmolStr::new_inline("put a channel"),
+        [user, ..] => bot.gen_user_message(ctx.channel_id, user, rng),
+        [] => bot.gen_message(ctx.channel_id, ctx.guild_id, None, rng),
+    }
+}
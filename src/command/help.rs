@@ -0,0 +1,38 @@
+use async_trait::async_trait;
+
+use super::{Arg, ArgKind, Command, Context, Reply};
+use crate::BotError;
+
+pub struct Help;
+
+const ARGS: &[Arg] = &[Arg::optional("command", ArgKind::Text, "")];
+
+#[async_trait]
+impl Command for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn description(&self) -> &'static str {
+        "posts this text"
+    }
+
+    fn help(&self) -> &'static str {
+        "lists every command, or explains one of them"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        ARGS
+    }
+
+    async fn run(&self, ctx: &Context<'_>) -> Result<Reply, BotError> {
+        let commands = ctx.bot.commands();
+        Ok(match ctx.args.first() {
+            Some(name) => match commands.get(name) {
+                Some(command) => Reply::Text(command.render_help().into()),
+                None => Reply::Unrecognised((*name).into()),
+            },
+            None => Reply::Text(commands.render_help().into()),
+        })
+    }
+}
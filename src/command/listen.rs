@@ -0,0 +1,138 @@
+use async_trait::async_trait;
+use smol_str::SmolStr;
+
+use super::{Arg, ArgKind, Command, Context, Permission, Reply, Subcommand};
+use crate::{perms::Level, BotError, CHANNEL_MARK_MSG};
+
+pub struct Listen;
+
+const SUBCOMMANDS: &[Subcommand] = &[
+    Subcommand {
+        name: "learn",
+        description: "toggle learning, which feeds the messages sent in this channel to its chains (and the corpus, if the bot keeps one)",
+        permission: Permission::Level(Level::Moderator),
+        args: &[],
+    },
+    Subcommand {
+        name: "speak",
+        description: "toggle speaking, which randomly posts messages generated from this channel's chains. `gen` works either way.",
+        permission: Permission::Level(Level::Moderator),
+        args: &[],
+    },
+    Subcommand {
+        name: "prob",
+        description: "get or set the probability of posting a message, as a percentage. an invalid value sets it to `5`.",
+        permission: Permission::LevelToChange(Level::Moderator),
+        args: &[Arg::optional("value", ArgKind::Number, "")],
+    },
+    Subcommand {
+        name: "order",
+        description: "get or set how many previous words the chain looks at, from 1 to 4. chains of orders used before are kept, so switching back doesn't lose anything.",
+        permission: Permission::LevelToChange(Level::Moderator),
+        args: &[Arg::optional("n", ArgKind::Integer, "")],
+    },
+    Subcommand {
+        name: "filter",
+        description: "show which messages are learned, or change it",
+        permission: Permission::LevelToChange(Level::Moderator),
+        args: &[
+            Arg::optional("setting", ArgKind::Text, "one of
+    - `bots <on|off>`: ignore messages from bots
+    - `links <on|off>`: ignore messages with links
+    - `min <n>` / `max <n>`: ignore messages shorter / longer than `n` characters, `max 0` means no limit
+    - `deny <regex>` / `allow <regex>`: add / remove a pattern, messages matching any are ignored
+    - `ignore <user id>` / `unignore <user id>`: ignore messages from a user"),
+            Arg::optional("value", ArgKind::Rest, ""),
+        ],
+    },
+    Subcommand {
+        name: "clear",
+        description: "forget everything learned in this channel. only does anything with `confirm` at the end.",
+        permission: Permission::Level(Level::Moderator),
+        args: &[
+            Arg::optional("scope", ArgKind::Text, "`user <id>` to only forget a user, `all` to forget every channel of this server"),
+            Arg::optional("confirm", ArgKind::Text, ""),
+        ],
+    },
+    Subcommand {
+        name: "rebuild",
+        description: "rebuild the chains from every message recorded in this channel. only works if the bot keeps a corpus.",
+        permission: Permission::Level(Level::Moderator),
+        args: &[],
+    },
+    Subcommand {
+        name: "server",
+        description: "toggle the server chain, which learns from every listened channel of this server. when the bot keeps a corpus it's built from everything recorded so far, and rebuilt whenever something is cleared or forgotten.",
+        permission: Permission::Level(Level::Moderator),
+        args: &[],
+    },
+];
+
+#[async_trait]
+impl Command for Listen {
+    fn name(&self) -> &'static str {
+        "listen"
+    }
+
+    fn description(&self) -> &'static str {
+        "markov chain listener management commands"
+    }
+
+    fn help(&self) -> &'static str {
+        "markov chain listener management commands
+
+if called with no arguments it will turn both learning and speaking on for the current channel, or both off if either is on"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Level(Level::Moderator)
+    }
+
+    fn subcommands(&self) -> &'static [Subcommand] {
+        SUBCOMMANDS
+    }
+
+    fn bare_name(&self) -> Option<&'static str> {
+        Some("toggle")
+    }
+
+    fn is_slow(&self, args: &[&str]) -> bool {
+        matches!(args, ["clear" | "rebuild" | "server", ..])
+    }
+
+    async fn run(&self, ctx: &Context<'_>) -> Result<Reply, BotError> {
+        let (bot, channel_id) = (ctx.bot, ctx.channel_id);
+        let text = match ctx.args {
+            [] => bot.markov_toggle_mark_channel(channel_id, ctx.guild_id),
+            ["learn", ..] => bot.markov_toggle_learn(channel_id, ctx.guild_id),
+            ["speak", ..] => bot.markov_toggle_speak(channel_id, ctx.guild_id),
+            ["prob"] => bot.markov_get_prob(channel_id),
+            ["prob", value, ..] => bot.markov_set_prob(channel_id, value),
+            ["order"] => bot.markov_get_order(channel_id),
+            ["order", value, ..] => bot.markov_set_order(channel_id, value),
+            ["filter"] => bot.markov_get_filter(channel_id),
+            ["filter", setting, value @ ..] => {
+                bot.markov_set_filter(channel_id, setting, &value.join(" "))
+            }
+            ["clear", args @ ..] => {
+                bot.markov_clear(channel_id, ctx.guild_id, args.iter().copied())
+                    .await
+            }
+            ["rebuild", ..] => match bot.markov_rebuild(channel_id).await {
+                Ok(Some(count)) => {
+                    bot.guilds_rebuild([&channel_id.into()]).await;
+                    format!("rebuilt chains from {} messages", count).into()
+                }
+                Ok(None) if bot.corpus.is_none() => SmolStr::new_inline("no corpus is kept"),
+                Ok(None) => CHANNEL_MARK_MSG.into(),
+                Err(err) => return Err(BotError::Storage(err.into())),
+            },
+            ["server", ..] => match ctx.guild_id {
+                Some(guild_id) => bot.markov_toggle_guild(guild_id).await,
+                None => return Err(BotError::NotFound("server here".into())),
+            },
+            [cmd, ..] => return Ok(Reply::Unrecognised((*cmd).into())),
+        };
+        Ok(Reply::Text(text))
+    }
+}
@@ -0,0 +1,89 @@
+use async_trait::async_trait;
+use smol_str::SmolStr;
+
+use super::{Arg, ArgKind, Command, Context, Permission, Reply, Subcommand};
+use crate::BotError;
+
+pub struct Owner;
+
+const SUBCOMMANDS: &[Subcommand] = &[
+    Subcommand {
+        name: "save",
+        description: "save everything that changed now",
+        permission: Permission::BotOwner,
+        args: &[],
+    },
+    Subcommand {
+        name: "stats",
+        description: "what the bot knows about, on every platform",
+        permission: Permission::BotOwner,
+        args: &[],
+    },
+    Subcommand {
+        name: "reload",
+        description: "read the poems and insults in the resources directory again",
+        permission: Permission::BotOwner,
+        args: &[],
+    },
+    Subcommand {
+        name: "leave",
+        description: "make the bot leave a server of this platform",
+        permission: Permission::BotOwner,
+        args: &[Arg::required("server", ArgKind::Text, "id of the server")],
+    },
+    Subcommand {
+        name: "broadcast",
+        description: "send a message to every channel of this platform the bot speaks in",
+        permission: Permission::BotOwner,
+        args: &[Arg::required("message", ArgKind::Rest, "")],
+    },
+];
+
+#[async_trait]
+impl Command for Owner {
+    fn name(&self) -> &'static str {
+        "owner"
+    }
+
+    fn description(&self) -> &'static str {
+        "commands for the users running the bot"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::BotOwner
+    }
+
+    fn subcommands(&self) -> &'static [Subcommand] {
+        SUBCOMMANDS
+    }
+
+    async fn run(&self, ctx: &Context<'_>) -> Result<Reply, BotError> {
+        let bot = ctx.bot;
+        let text = match ctx.args {
+            ["save", ..] => match bot.save().await {
+                Ok(()) => SmolStr::new_inline("saved"),
+                Err(err) => return Err(BotError::Storage(err)),
+            },
+            ["stats", ..] => bot.owner_stats(),
+            ["reload", ..] => match bot.reload_resources() {
+                Some(Ok(resources)) => format!(
+                    "reloaded {} poems and {} insults",
+                    resources.poems().count(),
+                    resources.insults().count()
+                )
+                .into(),
+                Some(Err(err)) => return Err(BotError::Storage(err.into())),
+                None => return Err(BotError::NotFound("resources directory configured".into())),
+            },
+            ["leave", guild_id, ..] => return Ok(Reply::LeaveGuild((*guild_id).into())),
+            ["leave"] => SmolStr::new_inline("put a server id"),
+            ["broadcast", message @ ..] if !message.is_empty() => {
+                return Ok(Reply::Broadcast(message.join(" ").into()))
+            }
+            ["broadcast"] => SmolStr::new_inline("put a message"),
+            [cmd, ..] => return Ok(Reply::Unrecognised((*cmd).into())),
+            [] => (self as &dyn Command).render_help().into(),
+        };
+        Ok(Reply::Text(text))
+    }
+}
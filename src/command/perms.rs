@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+use smol_str::SmolStr;
+
+use super::{Arg, ArgKind, Command, Context, Permission, Reply, Subcommand};
+use crate::{perms::Level, BotError};
+
+pub struct Perms;
+
+const SUBCOMMANDS: &[Subcommand] = &[
+    Subcommand {
+        name: "grant",
+        description: "make a user a bot admin",
+        permission: Permission::Level(Level::Admin),
+        args: &[Arg::required("user", ArgKind::User, "")],
+    },
+    Subcommand {
+        name: "revoke",
+        description: "make a user not a bot admin anymore",
+        permission: Permission::Level(Level::Admin),
+        args: &[Arg::required("user", ArgKind::User, "")],
+    },
+    Subcommand {
+        name: "grantrole",
+        description: "make everyone with a role a bot admin",
+        permission: Permission::Level(Level::Admin),
+        args: &[Arg::required("role", ArgKind::Role, "")],
+    },
+    Subcommand {
+        name: "revokerole",
+        description: "make everyone with a role not a bot admin anymore",
+        permission: Permission::Level(Level::Admin),
+        args: &[Arg::required("role", ArgKind::Role, "")],
+    },
+    Subcommand {
+        name: "level",
+        description: "change the level a command needs in this server",
+        permission: Permission::Level(Level::Admin),
+        args: &[
+            Arg::required(
+                "level",
+                ArgKind::Text,
+                "one of `user`, `moderator`, `admin` and `owner`",
+            ),
+            Arg::required(
+                "command",
+                ArgKind::Rest,
+                "a command, or a command and one of its subcommands",
+            ),
+        ],
+    },
+    Subcommand {
+        name: "reset",
+        description: "go back to the level a command needs by default",
+        permission: Permission::Level(Level::Admin),
+        args: &[Arg::required(
+            "command",
+            ArgKind::Rest,
+            "a command, or a command and one of its subcommands",
+        )],
+    },
+];
+
+#[async_trait]
+impl Command for Perms {
+    fn name(&self) -> &'static str {
+        "perms"
+    }
+
+    fn description(&self) -> &'static str {
+        "who can run which command"
+    }
+
+    fn help(&self) -> &'static str {
+        "who can run which command
+
+if called with no arguments it will show the bot admins and changed command levels of this server
+users are a `user`, `moderator`, `admin` or `owner` depending on their permissions in the server, and bot admins are always at least `admin`"
+    }
+
+    fn subcommands(&self) -> &'static [Subcommand] {
+        SUBCOMMANDS
+    }
+
+    fn bare_name(&self) -> Option<&'static str> {
+        Some("show")
+    }
+
+    async fn run(&self, ctx: &Context<'_>) -> Result<Reply, BotError> {
+        let (bot, context_id) = (ctx.bot, ctx.context_id);
+        let text = match ctx.args {
+            [] => bot.perms_show(context_id),
+            ["grant", user, ..] => bot.perms_set_admin(context_id, user, true),
+            ["revoke", user, ..] => bot.perms_set_admin(context_id, user, false),
+            ["grantrole", role, ..] => bot.perms_set_admin_role(context_id, role, true),
+            ["revokerole", role, ..] => bot.perms_set_admin_role(context_id, role, false),
+            ["grant" | "revoke"] => SmolStr::new_inline("put a user"),
+            ["grantrole" | "revokerole"] => SmolStr::new_inline("put a role"),
+            ["level", level, command @ ..] => {
+                bot.perms_set_level(context_id, command, Some(level))?
+            }
+            ["level"] => SmolStr::new_inline("put a level"),
+            ["reset", command @ ..] => bot.perms_set_level(context_id, command, None)?,
+            [cmd, ..] => return Ok(Reply::Unrecognised((*cmd).into())),
+        };
+        Ok(Reply::Text(text))
+    }
+}
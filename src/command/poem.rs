@@ -0,0 +1,85 @@
+use async_trait::async_trait;
+
+use super::{Arg, ArgKind, Command, Context, Permission, Reply, Subcommand};
+use crate::BotError;
+
+pub struct Poem;
+
+const ARGS: &[Arg] = &[Arg::optional(
+    "keywords",
+    ArgKind::Rest,
+    "words to search the poems for, or `#<n>` for the poem numbered n",
+)];
+
+const SUBCOMMANDS: &[Subcommand] = &[Subcommand {
+    name: "search",
+    description: "list the poems matching a search, best first",
+    permission: Permission::Everyone,
+    args: &[
+        Arg::required(
+            "query",
+            ArgKind::Rest,
+            "words to look for, in quotes to only find them together",
+        ),
+        Arg::optional("page", ArgKind::Integer, ""),
+    ],
+}];
+
+#[async_trait]
+impl Command for Poem {
+    fn name(&self) -> &'static str {
+        "poem"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["poems"]
+    }
+
+    fn description(&self) -> &'static str {
+        "search / get random poem"
+    }
+
+    fn help(&self) -> &'static str {
+        "search / get random poem
+
+if called with no arguments it will get a random poem, otherwise the one matching the keywords best
+words in quotes only match poems where they are together, like `poem \"the girl cried\"`"
+    }
+
+    fn args(&self) -> &'static [Arg] {
+        ARGS
+    }
+
+    fn subcommands(&self) -> &'static [Subcommand] {
+        SUBCOMMANDS
+    }
+
+    fn bare_name(&self) -> Option<&'static str> {
+        Some("get")
+    }
+
+    async fn run(&self, ctx: &Context<'_>) -> Result<Reply, BotError> {
+        let bot = ctx.bot;
+        let text = match ctx.args {
+            ["search"] => {
+                return Err(BotError::InvalidArgument(
+                    "put something to search for".into(),
+                ))
+            }
+            ["search", words @ ..] => {
+                // a number at the end is the page
+                let (query, page) = match words.split_last() {
+                    Some((page, query)) if !query.is_empty() => match page.parse() {
+                        Ok(page) => (query, page),
+                        Err(_) => (words, 1),
+                    },
+                    _ => (words, 1),
+                };
+                bot.poem_search(&query.join(" "), page)?
+            }
+            [number] if number.starts_with('#') => bot.poem_by_number(number)?,
+            keywords => bot.process_poem_command(&keywords.join(" ")),
+        };
+        Ok(Reply::Text(text))
+    }
+}
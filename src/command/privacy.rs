@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+
+use super::{Command, Context, Permission, Reply, Subcommand};
+use crate::{namespaced, BotError};
+
+pub struct Privacy;
+
+const SUBCOMMANDS: &[Subcommand] = &[
+    Subcommand {
+        name: "optout",
+        description: "stop learning from your messages",
+        permission: Permission::Everyone,
+        args: &[],
+    },
+    Subcommand {
+        name: "optin",
+        description: "start learning from your messages again",
+        permission: Permission::Everyone,
+        args: &[],
+    },
+    Subcommand {
+        name: "forget",
+        description: "forget everything you said in every channel. channels that learned from you before the bot kept a corpus can't forget you until they're cleared",
+        permission: Permission::Everyone,
+        args: &[],
+    },
+];
+
+#[async_trait]
+impl Command for Privacy {
+    fn name(&self) -> &'static str {
+        "privacy"
+    }
+
+    fn description(&self) -> &'static str {
+        "control what the bot learns from you"
+    }
+
+    fn subcommands(&self) -> &'static [Subcommand] {
+        SUBCOMMANDS
+    }
+
+    fn is_slow(&self, args: &[&str]) -> bool {
+        args.first() == Some(&"forget")
+    }
+
+    async fn run(&self, ctx: &Context<'_>) -> Result<Reply, BotError> {
+        let bot = ctx.bot;
+        let user_id = namespaced(ctx.platform, ctx.author);
+        let text = match ctx.args.first().copied() {
+            Some("optout") => bot.privacy_set_opt_out(&user_id, true),
+            Some("optin") => bot.privacy_set_opt_out(&user_id, false),
+            Some("forget") => bot.privacy_forget(ctx.platform, ctx.author).await,
+            Some(cmd) => return Ok(Reply::Unrecognised(cmd.into())),
+            None => (self as &dyn Command).render_help().into(),
+        };
+        Ok(Reply::Text(text))
+    }
+}
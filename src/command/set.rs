@@ -0,0 +1,62 @@
+use async_trait::async_trait;
+use smol_str::SmolStr;
+
+use super::{Arg, ArgKind, Command, Context, Permission, Reply, Subcommand};
+use crate::{perms::Level, BotError};
+
+pub struct Set;
+
+const SUBCOMMANDS: &[Subcommand] = &[
+    Subcommand {
+        name: "prefix",
+        description: "set the command prefix",
+        permission: Permission::Level(Level::Moderator),
+        args: &[Arg::required("prefix", ArgKind::Text, "")],
+    },
+    Subcommand {
+        name: "insult",
+        description: "toggle randomly insulting people",
+        permission: Permission::Level(Level::Moderator),
+        args: &[],
+    },
+];
+
+#[async_trait]
+impl Command for Set {
+    fn name(&self) -> &'static str {
+        "set"
+    }
+
+    fn description(&self) -> &'static str {
+        "change server settings"
+    }
+
+    fn permission(&self) -> Permission {
+        Permission::Level(Level::Moderator)
+    }
+
+    fn subcommands(&self) -> &'static [Subcommand] {
+        SUBCOMMANDS
+    }
+
+    async fn run(&self, ctx: &Context<'_>) -> Result<Reply, BotError> {
+        Ok(match ctx.args {
+            ["prefix", value, ..] => {
+                ctx.bot.data.set_prefix(ctx.context_id, value);
+                Reply::Text(format!("prefix is now `{}`.", value).into())
+            }
+            ["prefix"] => Reply::Text(SmolStr::new_inline("no value")),
+            ["insult", ..] => {
+                let mut m = ctx.bot.insult_entry(ctx.context_id);
+                m.enabled = !m.enabled;
+                if m.enabled {
+                    Reply::Text(SmolStr::new_inline("turned on insults"))
+                } else {
+                    Reply::Text(SmolStr::new_inline("turned off insults"))
+                }
+            }
+            [cmd, ..] => Reply::Unrecognised((*cmd).into()),
+            [] => Reply::Unrecognised(self.name().into()),
+        })
+    }
+}
@@ -0,0 +1,293 @@
+//! Runtime configuration.
+//!
+//! The config is read from the RON file at `BERNBOT_CONFIG` (`bernbot.ron` by
+//! default, which may be missing), after which every option can be overridden
+//! with its `BERNBOT_*` environment variable. See `bernbot.example.ron` for all
+//! options.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use rand::Rng;
+use serde::Deserialize;
+use smol_str::SmolStr;
+
+use crate::ratelimit::RateLimit;
+
+pub const CONFIG_PATH: &str = "bernbot.ron";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Command prefix used in channels that didn't set their own.
+    pub prefix: SmolStr,
+    /// Status shown on platforms that support it.
+    pub presence: String,
+    /// Adapters to start, every available one if empty.
+    pub adapters: Vec<String>,
+    /// Storage backend, `file` or `sqlite`.
+    pub storage: String,
+    /// Where the data is saved, depends on the backend if not set.
+    pub data_path: Option<PathBuf>,
+    /// Where the messages the chains are fed are logged, so they can be
+    /// rebuilt later. Nothing is logged if not set.
+    pub corpus_dir: Option<PathBuf>,
+    /// Seconds between autosaves.
+    pub autosave_period: u64,
+    /// How long the bot "types" before sending a message, in milliseconds.
+    pub typing_delay_ms: (u64, u64),
+    pub log_file: PathBuf,
+    /// Seed of the RNG everything is generated with, random if not set.
+    pub rng_seed: Option<u64>,
+    /// Directory with `poems.txt` and `insults.txt` to use instead of the
+    /// built in ones.
+    pub resources_dir: Option<PathBuf>,
+    /// Namespaced ids (`platform:id`) of the users who run the bot, who can
+    /// use the `owner` commands and are owners everywhere.
+    pub owners: Vec<SmolStr>,
+    /// Commands a user can run, across every channel. Not limited if not set.
+    pub user_rate_limit: Option<RateLimit>,
+    /// Commands run and messages sent in a channel. Not limited if not set.
+    pub channel_rate_limit: Option<RateLimit>,
+    /// Commands run and messages sent in a server. Not limited if not set.
+    pub guild_rate_limit: Option<RateLimit>,
+    /// Seconds a user has to wait before running a command again, by command
+    /// name.
+    pub cooldowns: BTreeMap<SmolStr, u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            prefix: SmolStr::new_inline("b/"),
+            presence: "G-go for it, yay. Mii, nipah~☆".into(),
+            adapters: Vec::new(),
+            storage: "file".into(),
+            data_path: None,
+            corpus_dir: None,
+            autosave_period: 60 * 60, // save every hour
+            typing_delay_ms: (400, 800),
+            log_file: "log".into(),
+            rng_seed: None,
+            resources_dir: None,
+            owners: Vec::new(),
+            user_rate_limit: Some(RateLimit {
+                burst: 5,
+                per_minute: 10,
+            }),
+            channel_rate_limit: Some(RateLimit {
+                burst: 10,
+                per_minute: 30,
+            }),
+            guild_rate_limit: Some(RateLimit {
+                burst: 30,
+                per_minute: 120,
+            }),
+            cooldowns: [("fuckyou", 10), ("gen", 3), ("poem", 3)]
+                .into_iter()
+                .map(|(name, secs)| (name.into(), secs))
+                .collect(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file, applies environment overrides and validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let (path, required) = match std::env::var("BERNBOT_CONFIG") {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(CONFIG_PATH), false),
+        };
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(raw) => ron::from_str(&raw).map_err(|err| ConfigError::Parse(path, err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => Self::default(),
+            Err(err) => return Err(ConfigError::Io(path, err)),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        env_override("BERNBOT_PREFIX", &mut self.prefix)?;
+        env_override("BERNBOT_PRESENCE", &mut self.presence)?;
+        env_override("BERNBOT_STORAGE", &mut self.storage)?;
+        env_override("BERNBOT_AUTOSAVE_PERIOD", &mut self.autosave_period)?;
+        env_override("BERNBOT_LOG_FILE", &mut self.log_file)?;
+        if let Ok(path) = std::env::var("BERNBOT_DATA_PATH") {
+            self.data_path = Some(path.into());
+        }
+        if let Ok(value) = std::env::var("BERNBOT_RNG_SEED") {
+            let seed = value.parse().map_err(|_| ConfigError::InvalidEnv {
+                var: "BERNBOT_RNG_SEED",
+                value,
+            })?;
+            self.rng_seed = Some(seed);
+        }
+        if let Ok(dir) = std::env::var("BERNBOT_CORPUS_DIR") {
+            self.corpus_dir = Some(dir.into()).filter(|dir: &PathBuf| !dir.as_os_str().is_empty());
+        }
+        if let Ok(dir) = std::env::var("BERNBOT_RESOURCES_DIR") {
+            self.resources_dir =
+                Some(dir.into()).filter(|dir: &PathBuf| !dir.as_os_str().is_empty());
+        }
+        if let Ok(list) = std::env::var("BERNBOT_OWNERS") {
+            self.owners = list
+                .split(',')
+                .map(|id| id.trim().into())
+                .filter(|id: &SmolStr| !id.is_empty())
+                .collect();
+        }
+        rate_limit_override("BERNBOT_USER_RATE_LIMIT", &mut self.user_rate_limit)?;
+        rate_limit_override("BERNBOT_CHANNEL_RATE_LIMIT", &mut self.channel_rate_limit)?;
+        rate_limit_override("BERNBOT_GUILD_RATE_LIMIT", &mut self.guild_rate_limit)?;
+        if let Ok(list) = std::env::var("BERNBOT_COOLDOWNS") {
+            self.cooldowns = list
+                .split(',')
+                .filter(|entry| !entry.trim().is_empty())
+                .map(|entry| {
+                    let (name, secs) = entry.split_once('=')?;
+                    Some((name.trim().into(), secs.trim().parse().ok()?))
+                })
+                .collect::<Option<_>>()
+                .ok_or(ConfigError::InvalidEnv {
+                    var: "BERNBOT_COOLDOWNS",
+                    value: list,
+                })?;
+        }
+        if let Ok(list) = std::env::var("BERNBOT_ADAPTERS") {
+            self.adapters = list
+                .split(',')
+                .map(|name| name.trim().to_owned())
+                .filter(|name| !name.is_empty())
+                .collect();
+        }
+        if let Ok(value) = std::env::var("BERNBOT_TYPING_DELAY") {
+            let parse = |s: &str| s.trim().parse().ok();
+            self.typing_delay_ms = match value.split_once('-') {
+                Some((min, max)) => parse(min).zip(parse(max)),
+                None => parse(&value).map(|ms| (ms, ms)),
+            }
+            .ok_or(ConfigError::InvalidEnv {
+                var: "BERNBOT_TYPING_DELAY",
+                value,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.prefix.is_empty() || self.prefix.contains(char::is_whitespace) {
+            return Err(ConfigError::Invalid(
+                "prefix can't be empty or contain whitespace",
+            ));
+        }
+        if self.autosave_period == 0 {
+            return Err(ConfigError::Invalid("autosave period can't be zero"));
+        }
+        if self.typing_delay_ms.0 > self.typing_delay_ms.1 {
+            return Err(ConfigError::Invalid(
+                "minimum typing delay can't be more than the maximum",
+            ));
+        }
+        if self.owners.iter().any(|id| !id.contains(':')) {
+            return Err(ConfigError::Invalid(
+                "owners have to be namespaced ids, like `discord:1234`",
+            ));
+        }
+        let limits = [
+            self.user_rate_limit,
+            self.channel_rate_limit,
+            self.guild_rate_limit,
+        ];
+        if limits
+            .into_iter()
+            .flatten()
+            .any(|limit| limit.burst == 0 || limit.per_minute == 0)
+        {
+            return Err(ConfigError::Invalid(
+                "rate limits can't have a zero burst or rate, leave them out instead",
+            ));
+        }
+        if self.log_file.file_name().is_none() {
+            return Err(ConfigError::Invalid("log file has to be a file"));
+        }
+        Ok(())
+    }
+
+    pub fn data_path(&self) -> &Path {
+        match &self.data_path {
+            Some(path) => path,
+            None if self.storage == "sqlite" => Path::new("data.sqlite"),
+            None => Path::new("data"),
+        }
+    }
+
+    pub fn autosave_period(&self) -> Duration {
+        Duration::from_secs(self.autosave_period)
+    }
+
+    /// A random delay in the configured typing delay range.
+    pub fn typing_delay(&self, rng: &mut impl Rng) -> Duration {
+        let (min, max) = self.typing_delay_ms;
+        Duration::from_millis(rng.gen_range(min..=max))
+    }
+}
+
+fn env_override<T: FromStr>(var: &'static str, target: &mut T) -> Result<(), ConfigError> {
+    if let Ok(value) = std::env::var(var) {
+        *target = value
+            .parse()
+            .map_err(|_| ConfigError::InvalidEnv { var, value })?;
+    }
+    Ok(())
+}
+
+/// Like [`env_override`], with `off` (or nothing) for no limit.
+fn rate_limit_override(
+    var: &'static str,
+    target: &mut Option<RateLimit>,
+) -> Result<(), ConfigError> {
+    if let Ok(value) = std::env::var(var) {
+        *target = match value.trim() {
+            "" | "off" => None,
+            limit => Some(
+                limit
+                    .parse()
+                    .map_err(|_| ConfigError::InvalidEnv { var, value })?,
+            ),
+        };
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, ron::error::SpannedError),
+    InvalidEnv { var: &'static str, value: String },
+    Invalid(&'static str),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "couldnt read `{}`: {}", path.display(), err),
+            ConfigError::Parse(path, err) => {
+                write!(f, "couldnt parse `{}`: {}", path.display(), err)
+            }
+            ConfigError::InvalidEnv { var, value } => {
+                write!(f, "invalid value `{}` for {}", value, var)
+            }
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
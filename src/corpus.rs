@@ -0,0 +1,361 @@
+//! Append-only log of the messages the markov chains were fed, so they can be
+//! rebuilt from scratch.
+//!
+//! Every channel gets its own file in the corpus directory. A file is a
+//! sequence of records, each being its length as a little endian `u32`
+//! followed by an [`Entry`] as lz4 compressed RON. A record cut off by a crash
+//! mid-write is ignored when reading, and cut off before anything is appended
+//! after it.
+
+use std::{
+    collections::HashSet,
+    fmt::Write as _,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use smol_str::SmolStr;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Entry {
+    pub author: SmolStr,
+    #[serde(default)]
+    pub author_is_bot: bool,
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    pub content: String,
+}
+
+impl Entry {
+    pub fn new(author: &str, author_is_bot: bool, content: &str) -> Self {
+        Self {
+            author: author.into(),
+            author_is_bot,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            content: content.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Corpus {
+    dir: PathBuf,
+    /// Held while writing. Has the channels whose file is known to end with a
+    /// whole record.
+    write_lock: Mutex<HashSet<SmolStr>>,
+}
+
+impl Corpus {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_owned(),
+            write_lock: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// The file the corpus of `channel_id` is kept in. Characters that can't
+    /// be used in file names everywhere are escaped as `%XX`.
+    fn path(&self, channel_id: &str) -> PathBuf {
+        let mut name = String::with_capacity(channel_id.len() + 4);
+        for byte in channel_id.bytes() {
+            if byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'-' | b'_') {
+                name.push(byte as char);
+            } else {
+                let _ = write!(name, "%{:02X}", byte);
+            }
+        }
+        name.push_str(".log");
+        self.dir.join(name)
+    }
+
+    /// Ids of every channel that has a corpus.
+    pub fn channels(&self) -> io::Result<Vec<SmolStr>> {
+        let dir = match std::fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut channels = Vec::new();
+        for file in dir {
+            let name = file?.file_name();
+            if let Some(channel_id) = name.to_str().and_then(unescape) {
+                channels.push(channel_id);
+            }
+        }
+        Ok(channels)
+    }
+
+    pub fn append(&self, channel_id: &str, entry: &Entry) -> io::Result<()> {
+        let record = encode(entry)?;
+        let mut checked = self.write_lock.lock();
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(channel_id);
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+        if !checked.contains(channel_id) {
+            truncate_torn_record(&path, &mut file)?;
+            checked.insert(channel_id.into());
+        }
+        file.write_all(&record)
+    }
+
+    /// Removes every entry of `author` from the corpus of `channel_id`,
+    /// returning how many there were. This blocks, since new entries can't be
+    /// appended while the file is rewritten.
+    pub fn remove_author(&self, channel_id: &str, author: &str) -> io::Result<usize> {
+        let path = self.path(channel_id);
+        let _guard = self.write_lock.lock();
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) => decode(&path, &bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+
+        let mut bytes = Vec::new();
+        let mut removed = 0;
+        for entry in &entries {
+            if entry.author == author {
+                removed += 1;
+            } else {
+                bytes.extend(encode(entry)?);
+            }
+        }
+        if removed > 0 {
+            let mut tmp_path = path.clone().into_os_string();
+            tmp_path.push(".tmp");
+            let mut file = std::fs::File::create(&tmp_path)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+            std::fs::rename(&tmp_path, &path)?;
+        }
+        Ok(removed)
+    }
+
+    /// Deletes the corpus of `channel_id`.
+    pub fn remove(&self, channel_id: &str) -> io::Result<()> {
+        let _guard = self.write_lock.lock();
+        match std::fs::remove_file(self.path(channel_id)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads every entry logged for `channel_id`, oldest first.
+    pub async fn read(&self, channel_id: &str) -> io::Result<Vec<Entry>> {
+        let path = self.path(channel_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => decode(&path, &bytes),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+fn encode(entry: &Entry) -> io::Result<Vec<u8>> {
+    let raw = ron::ser::to_string(entry).map_err(io::Error::other)?;
+    let compressed = lz4_flex::compress_prepend_size(raw.as_bytes());
+    let mut record = Vec::with_capacity(4 + compressed.len());
+    record.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+    record.extend_from_slice(&compressed);
+    Ok(record)
+}
+
+fn decode(path: &Path, bytes: &[u8]) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let record = match rest {
+            [l0, l1, l2, l3, tail @ ..] => {
+                let len = u32::from_le_bytes([*l0, *l1, *l2, *l3]) as usize;
+                tail.get(..len).map(|record| (record, &tail[len..]))
+            }
+            _ => None,
+        };
+        let (record, tail) = match record {
+            Some(record) => record,
+            None => {
+                tracing::warn!(
+                    "ignoring truncated record at the end of `{}`",
+                    path.display()
+                );
+                break;
+            }
+        };
+        let raw = lz4_flex::decompress_size_prepended(record)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let entry = ron::de::from_bytes(&raw)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        entries.push(entry);
+        rest = tail;
+    }
+    Ok(entries)
+}
+
+/// Cuts off a record left half written at the end of `file` by a crash, so
+/// the records appended after it can be read.
+fn truncate_torn_record(path: &Path, file: &mut File) -> io::Result<()> {
+    let len = file.metadata()?.len();
+    let mut end = 0;
+    let mut header = [0; 4];
+    while end + 4 <= len {
+        file.seek(SeekFrom::Start(end))?;
+        file.read_exact(&mut header)?;
+        let next = end + 4 + u32::from_le_bytes(header) as u64;
+        if next > len {
+            break;
+        }
+        end = next;
+    }
+    if end < len {
+        tracing::warn!(
+            "cutting off truncated record at the end of `{}`",
+            path.display()
+        );
+        file.set_len(end)?;
+    }
+    Ok(())
+}
+
+/// Turns a file name made by [`Corpus::path`] back into a channel id.
+fn unescape(name: &str) -> Option<SmolStr> {
+    let escaped = name.strip_suffix(".log")?.as_bytes();
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut i = 0;
+    while i < escaped.len() {
+        if escaped[i] == b'%' {
+            let hex = std::str::from_utf8(escaped.get(i + 1..i + 3)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(escaped[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(bytes).ok().map(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corpus(name: &str) -> Corpus {
+        let dir =
+            std::env::temp_dir().join(format!("bernbot-corpus-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Corpus::new(dir)
+    }
+
+    fn contents(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.content.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn append_and_read() {
+        let corpus = corpus("append");
+        assert!(corpus.read("discord:1").await.unwrap().is_empty());
+        corpus
+            .append("discord:1", &Entry::new("a", false, "one"))
+            .unwrap();
+        corpus
+            .append("discord:1", &Entry::new("b", true, "two"))
+            .unwrap();
+        corpus
+            .append("discord:2", &Entry::new("a", false, "three"))
+            .unwrap();
+
+        let entries = corpus.read("discord:1").await.unwrap();
+        assert_eq!(contents(&entries), ["one", "two"]);
+        assert!(entries[1].author_is_bot);
+        let mut channels = corpus.channels().unwrap();
+        channels.sort();
+        assert_eq!(channels, ["discord:1", "discord:2"]);
+    }
+
+    #[tokio::test]
+    async fn torn_records_are_cut_off_before_appending() {
+        for torn_len in [1, 3, 4, 10] {
+            let corpus = corpus("torn");
+            corpus
+                .append("discord:1", &Entry::new("a", false, "one"))
+                .unwrap();
+            corpus
+                .append("discord:1", &Entry::new("a", false, "two"))
+                .unwrap();
+            let path = corpus.path("discord:1");
+            let record = encode(&Entry::new("a", false, "torn")).unwrap();
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(&record[..torn_len]).unwrap();
+            drop(file);
+            assert_eq!(
+                contents(&corpus.read("discord:1").await.unwrap()),
+                ["one", "two"]
+            );
+
+            // like after a restart
+            let corpus = Corpus::new(&corpus.dir);
+            corpus
+                .append("discord:1", &Entry::new("a", false, "three"))
+                .unwrap();
+            corpus
+                .append("discord:1", &Entry::new("a", false, "four"))
+                .unwrap();
+            assert_eq!(
+                contents(&corpus.read("discord:1").await.unwrap()),
+                ["one", "two", "three", "four"],
+                "torn after {} bytes",
+                torn_len
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn remove_author() {
+        let corpus = corpus("remove_author");
+        corpus
+            .append("discord:1", &Entry::new("a", false, "one"))
+            .unwrap();
+        corpus
+            .append("discord:1", &Entry::new("b", false, "two"))
+            .unwrap();
+        corpus
+            .append("discord:1", &Entry::new("a", false, "three"))
+            .unwrap();
+        assert_eq!(corpus.remove_author("discord:1", "a").unwrap(), 2);
+        assert_eq!(corpus.remove_author("discord:1", "a").unwrap(), 0);
+        assert_eq!(corpus.remove_author("discord:2", "a").unwrap(), 0);
+        corpus
+            .append("discord:1", &Entry::new("a", false, "four"))
+            .unwrap();
+        assert_eq!(
+            contents(&corpus.read("discord:1").await.unwrap()),
+            ["two", "four"]
+        );
+
+        corpus.remove("discord:1").unwrap();
+        assert!(corpus.read("discord:1").await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn file_names() {
+        let corpus = corpus("file_names");
+        for channel_id in ["discord:123", "matrix:!room:example.org", "cli:a/b\\c"] {
+            let path = corpus.path(channel_id);
+            let name = path.file_name().unwrap().to_str().unwrap();
+            assert!(!name.contains([':', '/', '\\', '!']), "{}", name);
+            assert_eq!(unescape(name).unwrap(), channel_id);
+        }
+        assert_eq!(unescape("data.ron"), None);
+    }
+}
@@ -1,26 +1,78 @@
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::{BotError, Handler, PRESENCE_DEF};
+use crate::{
+    command::{Arg, ArgKind, Command},
+    perms::Level,
+    runtime::Shutdown,
+    BotError, Handler,
+};
 
 use super::{perr, Bot};
 use discord::{
     async_trait,
+    builder::{CreateApplicationCommand, CreateApplicationCommandOption, EditInteractionResponse},
     client::{Client, Context, EventHandler},
+    json::Value,
     model::{
         channel::{AttachmentType, Message},
+        id::{ChannelId, GuildId},
+        interactions::{
+            application_command::{
+                ApplicationCommand, ApplicationCommandInteraction,
+                ApplicationCommandInteractionDataOption, ApplicationCommandOptionType,
+            },
+            Interaction, InteractionResponseType,
+        },
         prelude::{Activity, Ready},
+        Permissions,
     },
     prelude::GatewayIntents,
     utils::ContentSafeOptions,
 };
-use rand::Rng;
 use smol_str::SmolStr;
 
-const DATA_PATH: &str = "data_discord";
+/// The level of a member with `permissions`, `owner` being whether they own
+/// the server.
+fn level(owner: bool, permissions: Permissions) -> Level {
+    if owner {
+        Level::Owner
+    } else if permissions.administrator() {
+        Level::Admin
+    } else if permissions.manage_guild() || permissions.manage_channels() {
+        Level::Moderator
+    } else {
+        Level::User
+    }
+}
+
+const NOT_AN_ID: discord::Error = discord::Error::Other("not a discord id");
+
+async fn leave_guild(ctx: &Context, guild_id: &str) -> Result<bool, BotError<discord::Error>> {
+    GuildId(guild_id.parse().map_err(|_| NOT_AN_ID)?)
+        .leave(&ctx.http)
+        .await?;
+    Ok(true)
+}
+
+async fn send_to(
+    ctx: &Context,
+    channel_id: &str,
+    text: &str,
+) -> Result<bool, BotError<discord::Error>> {
+    let content = discord::utils::content_safe(ctx, text, &ContentSafeOptions::default(), &[]);
+    ChannelId(channel_id.parse().map_err(|_| NOT_AN_ID)?)
+        .send_message(&ctx.http, |msg| {
+            msg.content(content).allowed_mentions(|c| c.empty_parse())
+        })
+        .await?;
+    Ok(true)
+}
 
 struct DiscordHandler<'a> {
     msg: &'a Message,
     ctx: &'a Context,
+    bot: &'a Bot,
+    bot_user_id: SmolStr,
     id: SmolStr,
     author: SmolStr,
     channel_id: SmolStr,
@@ -32,17 +84,34 @@ struct DiscordHandler<'a> {
 impl<'a> Handler for DiscordHandler<'a> {
     type Error = discord::Error;
 
-    async fn author_has_manage_perm(&self) -> Result<bool, BotError<Self::Error>> {
-        Ok(self
+    async fn author_level(&self) -> Result<Level, BotError<Self::Error>> {
+        let guild = self
             .msg
             .guild(self.ctx)
-            .ok_or(discord::Error::Model(
-                discord::model::ModelError::GuildNotFound,
-            ))?
+            .ok_or_else(|| BotError::NotFound("server here".into()))?;
+        let permissions = guild
             .member(self.ctx, self.msg.author.id)
             .await?
-            .permissions(self.ctx)?
-            .manage_webhooks())
+            .permissions(self.ctx)?;
+        Ok(level(guild.owner_id == self.msg.author.id, permissions))
+    }
+
+    async fn leave_guild(&self, guild_id: &str) -> Result<bool, BotError<Self::Error>> {
+        leave_guild(self.ctx, guild_id).await
+    }
+
+    async fn send_to(&self, channel_id: &str, text: &str) -> Result<bool, BotError<Self::Error>> {
+        send_to(self.ctx, channel_id, text).await
+    }
+
+    async fn author_roles(&self) -> Result<Vec<SmolStr>, BotError<Self::Error>> {
+        Ok(self.msg.member.as_ref().map_or_else(Vec::new, |member| {
+            member
+                .roles
+                .iter()
+                .map(|role| role.0.to_string().into())
+                .collect()
+        }))
     }
 
     async fn send_message(
@@ -55,8 +124,7 @@ impl<'a> Handler for DiscordHandler<'a> {
             discord::utils::content_safe(self.ctx, text, &ContentSafeOptions::default(), &[]);
 
         let typing = self.ctx.http.start_typing(self.msg.channel_id.0).unwrap();
-        let millis = rand::thread_rng().gen_range(400..=800);
-        tokio::time::sleep(Duration::from_millis(millis)).await;
+        tokio::time::sleep(self.bot.typing_delay()).await;
         let msg = self
             .msg
             .channel_id
@@ -78,6 +146,18 @@ impl<'a> Handler for DiscordHandler<'a> {
         Ok(msg.id.0.to_string().into())
     }
 
+    fn platform(&self) -> &str {
+        "discord"
+    }
+
+    fn bot_user_id(&self) -> &str {
+        &self.bot_user_id
+    }
+
+    fn author_is_bot(&self) -> bool {
+        self.msg.author.bot
+    }
+
     fn referenced_id(&self) -> Option<&str> {
         self.referenced_id.as_deref()
     }
@@ -103,15 +183,352 @@ impl<'a> Handler for DiscordHandler<'a> {
     }
 }
 
+/// A slash command. Its first reply responds to the interaction, or edits the
+/// response if it was deferred, the rest are followups.
+struct InteractionHandler<'a> {
+    interaction: &'a ApplicationCommandInteraction,
+    ctx: &'a Context,
+    bot_user_id: SmolStr,
+    id: SmolStr,
+    author: SmolStr,
+    channel_id: SmolStr,
+    guild_id: Option<SmolStr>,
+    responded: AtomicBool,
+    deferred: AtomicBool,
+}
+
+impl<'a> InteractionHandler<'a> {
+    async fn respond(
+        &self,
+        text: &str,
+        attach: Option<(&str, Vec<u8>)>,
+        ephemeral: bool,
+    ) -> Result<SmolStr, BotError<discord::Error>> {
+        let content =
+            discord::utils::content_safe(self.ctx, text, &ContentSafeOptions::default(), &[]);
+        let file = attach.map(|(name, data)| AttachmentType::Bytes {
+            data: data.into(),
+            filename: name.into(),
+        });
+
+        let msg = if self.deferred.swap(false, Ordering::SeqCst) {
+            // whether it's ephemeral was decided when it was deferred
+            let mut edit = EditInteractionResponse::default();
+            edit.content(content).allowed_mentions(|c| c.empty_parse());
+            let edit = Value::from(discord::json::hashmap_to_json_map(edit.0));
+            let (http, token) = (&self.ctx.http, &self.interaction.token);
+            match file {
+                None => {
+                    http.edit_original_interaction_response(token, &edit)
+                        .await?
+                }
+                // files can only be added when editing it by id, like a followup
+                Some(file) => {
+                    let original = self.interaction.get_interaction_response(http).await?;
+                    http.edit_followup_message_and_attachments(token, original.id.0, &edit, [file])
+                        .await?
+                }
+            }
+        } else if self.responded.swap(true, Ordering::SeqCst) {
+            self.interaction
+                .create_followup_message(&self.ctx.http, |msg| {
+                    msg.content(content)
+                        .allowed_mentions(|c| c.empty_parse())
+                        .ephemeral(ephemeral);
+                    if let Some(file) = file {
+                        msg.add_file(file);
+                    }
+                    msg
+                })
+                .await?
+        } else {
+            self.interaction
+                .create_interaction_response(&self.ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|msg| {
+                            msg.content(content)
+                                .allowed_mentions(|c| c.empty_parse())
+                                .ephemeral(ephemeral);
+                            if let Some(file) = file {
+                                msg.add_file(file);
+                            }
+                            msg
+                        })
+                })
+                .await?;
+            self.interaction
+                .get_interaction_response(&self.ctx.http)
+                .await?
+        };
+        Ok(msg.id.0.to_string().into())
+    }
+}
+
+#[async_trait]
+impl<'a> Handler for InteractionHandler<'a> {
+    type Error = discord::Error;
+
+    async fn author_level(&self) -> Result<Level, BotError<Self::Error>> {
+        let owner = self.interaction.guild_id.and_then(|id| {
+            self.ctx
+                .cache
+                .guild_field(id, |guild| guild.owner_id == self.interaction.user.id)
+        });
+        let permissions = self
+            .interaction
+            .member
+            .as_ref()
+            .and_then(|member| member.permissions)
+            .unwrap_or_else(Permissions::empty);
+        Ok(level(owner.unwrap_or(false), permissions))
+    }
+
+    async fn leave_guild(&self, guild_id: &str) -> Result<bool, BotError<Self::Error>> {
+        leave_guild(self.ctx, guild_id).await
+    }
+
+    async fn send_to(&self, channel_id: &str, text: &str) -> Result<bool, BotError<Self::Error>> {
+        send_to(self.ctx, channel_id, text).await
+    }
+
+    /// Discord only waits 3 seconds for a response, so this responds with
+    /// "thinking…" until the reply edits it.
+    async fn defer(&self, ephemeral: bool) -> Result<(), BotError<Self::Error>> {
+        if self.responded.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.interaction
+            .create_interaction_response(&self.ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|msg| msg.ephemeral(ephemeral))
+            })
+            .await?;
+        self.deferred.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn author_roles(&self) -> Result<Vec<SmolStr>, BotError<Self::Error>> {
+        Ok(self
+            .interaction
+            .member
+            .as_ref()
+            .map_or_else(Vec::new, |member| {
+                member
+                    .roles
+                    .iter()
+                    .map(|role| role.0.to_string().into())
+                    .collect()
+            }))
+    }
+
+    /// Interaction responses always answer the command, so `reply` is ignored.
+    async fn send_message(
+        &self,
+        text: &str,
+        attach: Option<(&str, Vec<u8>)>,
+        _reply: bool,
+    ) -> Result<SmolStr, BotError<Self::Error>> {
+        self.respond(text, attach, false).await
+    }
+
+    async fn send_ephemeral(
+        &self,
+        text: &str,
+        attach: Option<(&str, Vec<u8>)>,
+    ) -> Result<SmolStr, BotError<Self::Error>> {
+        self.respond(text, attach, true).await
+    }
+
+    fn platform(&self) -> &str {
+        "discord"
+    }
+
+    fn bot_user_id(&self) -> &str {
+        &self.bot_user_id
+    }
+
+    fn author_is_bot(&self) -> bool {
+        self.interaction.user.bot
+    }
+
+    fn referenced_id(&self) -> Option<&str> {
+        None
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn author(&self) -> &str {
+        &self.author
+    }
+
+    fn content(&self) -> &str {
+        ""
+    }
+
+    fn channel_id(&self) -> &str {
+        &self.channel_id
+    }
+
+    fn guild_id(&self) -> Option<&str> {
+        self.guild_id.as_deref()
+    }
+}
+
+/// Discord wants descriptions to be 1 to 100 characters long.
+fn slash_description(text: &str) -> String {
+    if text.chars().count() > 100 {
+        text.chars().take(99).chain(std::iter::once('…')).collect()
+    } else {
+        text.to_owned()
+    }
+}
+
+fn slash_option(arg: &Arg) -> CreateApplicationCommandOption {
+    let kind = match arg.kind {
+        ArgKind::Text | ArgKind::Rest => ApplicationCommandOptionType::String,
+        ArgKind::Integer => ApplicationCommandOptionType::Integer,
+        ArgKind::Number => ApplicationCommandOptionType::Number,
+        ArgKind::User => ApplicationCommandOptionType::User,
+        ArgKind::Channel => ApplicationCommandOptionType::Channel,
+        ArgKind::Role => ApplicationCommandOptionType::Role,
+    };
+    let description = if arg.description.is_empty() {
+        arg.name
+    } else {
+        arg.description
+    };
+    let mut option = CreateApplicationCommandOption::default();
+    option
+        .name(arg.name)
+        .description(slash_description(description))
+        .kind(kind)
+        .required(arg.required);
+    option
+}
+
+fn slash_subcommand(name: &str, description: &str, args: &[Arg]) -> CreateApplicationCommandOption {
+    let mut option = CreateApplicationCommandOption::default();
+    option
+        .name(name)
+        .description(slash_description(description))
+        .kind(ApplicationCommandOptionType::SubCommand);
+    for arg in args {
+        option.add_sub_option(slash_option(arg));
+    }
+    option
+}
+
+/// Declares `command` as a slash command. Discord doesn't let a command take
+/// options when it has subcommands, so running it without one becomes a
+/// subcommand called its [`Command::bare_name`].
+fn slash_command<'a>(
+    command: &dyn Command,
+    builder: &'a mut CreateApplicationCommand,
+) -> &'a mut CreateApplicationCommand {
+    builder
+        .name(command.name())
+        .description(slash_description(command.description()));
+    if command.subcommands().is_empty() {
+        for arg in command.args() {
+            builder.add_option(slash_option(arg));
+        }
+    } else {
+        if let Some(bare) = command.bare_name() {
+            builder.add_option(slash_subcommand(
+                bare,
+                command.description(),
+                command.args(),
+            ));
+        }
+        for sub in command.subcommands() {
+            builder.add_option(slash_subcommand(sub.name, sub.description, sub.args));
+        }
+    }
+    builder
+}
+
+/// The options of a slash command as the arguments they would be in a
+/// message, in the order the command takes them. Missing optional ones are
+/// left out.
+fn slash_args(
+    command: &dyn Command,
+    options: &[ApplicationCommandInteractionDataOption],
+) -> Vec<String> {
+    let mut args = Vec::new();
+    let (schema, options) = match options.first() {
+        Some(sub) if sub.kind == ApplicationCommandOptionType::SubCommand => {
+            if command.bare_name() == Some(sub.name.as_str()) {
+                (command.args(), sub.options.as_slice())
+            } else {
+                args.push(sub.name.clone());
+                let schema = command
+                    .subcommand(&sub.name)
+                    .map_or(&[][..], |sub| sub.args);
+                (schema, sub.options.as_slice())
+            }
+        }
+        _ => (command.args(), options),
+    };
+    for arg in schema {
+        let value = options
+            .iter()
+            .find(|option| option.name == arg.name)
+            .and_then(|option| option.value.as_ref());
+        match value {
+            Some(Value::String(text)) => args.extend(text.split_whitespace().map(str::to_owned)),
+            Some(value) => args.push(value.to_string()),
+            None => {}
+        }
+    }
+    args
+}
+
 #[async_trait]
 impl EventHandler for Bot {
     async fn ready(&self, ctx: Context, _data_about_bot: Ready) {
-        ctx.set_activity(Activity::playing(
-            option_env!("PRESENCE_MSG").unwrap_or(PRESENCE_DEF),
-        ))
-        .await;
+        ctx.set_activity(Activity::playing(&self.config().presence))
+            .await;
 
-        self.start_autosave_task(DATA_PATH);
+        let commands = self.commands();
+        perr!(
+            ApplicationCommand::set_global_application_commands(&ctx.http, |builder| {
+                for command in commands.iter() {
+                    builder.create_application_command(|c| slash_command(command, c));
+                }
+                builder
+            })
+            .await
+        );
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let interaction = match interaction {
+            Interaction::ApplicationCommand(interaction) => interaction,
+            _ => return,
+        };
+        let name = interaction.data.name.as_str();
+        let args = self.commands().get(name).map_or_else(Vec::new, |command| {
+            slash_args(command, &interaction.data.options)
+        });
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let handler = InteractionHandler {
+            interaction: &interaction,
+            ctx: &ctx,
+            bot_user_id: ctx.cache.current_user_id().0.to_string().into(),
+            id: interaction.id.0.to_string().into(),
+            author: interaction.user.id.0.to_string().into(),
+            channel_id: interaction.channel_id.0.to_string().into(),
+            guild_id: interaction.guild_id.map(|i| i.as_u64().to_string().into()),
+            responded: AtomicBool::new(false),
+            deferred: AtomicBool::new(false),
+        };
+
+        perr!(self.run_command(&handler, name, &args).await);
     }
 
     async fn message(&self, ctx: Context, new_message: Message) {
@@ -127,6 +544,8 @@ impl EventHandler for Bot {
         let handler = DiscordHandler {
             msg: &new_message,
             ctx: &ctx,
+            bot: self,
+            bot_user_id: ctx.cache.current_user_id().0.to_string().into(),
             channel_id,
             id,
             referenced_id,
@@ -138,33 +557,76 @@ impl EventHandler for Bot {
     }
 }
 
-pub async fn main() {
-    let token = std::env::var("DISCORD_TOKEN").expect("need token");
+pub async fn run(bot: Bot, mut shutdown: Shutdown) {
+    let token = match std::env::var("DISCORD_TOKEN") {
+        Ok(token) => token,
+        Err(_) => {
+            tracing::error!("DISCORD_TOKEN is not set, not starting discord");
+            return;
+        }
+    };
 
-    let user_id = discord::http::client::Http::new(&token)
-        .get_current_user()
-        .await
-        .expect("user")
-        .id
-        .0
-        .to_string();
-    let bot = Bot::read_from(DATA_PATH)
-        .await
-        .unwrap_or_else(|_| Bot::new(user_id.into()));
-    let bot2 = bot.clone();
-
-    let mut client = Client::builder(token, GatewayIntents::all())
+    let mut client = match Client::builder(token, GatewayIntents::all())
         .event_handler(bot)
         .await
-        .expect("Error creating client");
+    {
+        Ok(client) => client,
+        Err(err) => {
+            tracing::error!("couldnt create discord client: {}", err);
+            return;
+        }
+    };
 
     let cc = client.shard_manager.clone();
     tokio::spawn(async move {
-        tokio::signal::ctrl_c().await.unwrap();
+        shutdown.wait().await;
         cc.lock().await.shutdown_all().await;
-        bot2.save_to(DATA_PATH).await.expect("couldnt save");
-        std::process::exit(0);
     });
 
     perr!(client.start().await);
 }
+
+#[cfg(test)]
+mod tests {
+    use discord::json::{json, prelude::from_value};
+
+    use super::*;
+    use crate::command::Registry;
+
+    fn args(command: &str, options: Value) -> Vec<String> {
+        let registry = Registry::default();
+        let options = from_value::<Vec<ApplicationCommandInteractionDataOption>>(options).unwrap();
+        slash_args(registry.get(command).unwrap(), &options)
+    }
+
+    fn sub(name: &str, options: Value) -> Value {
+        json!([{ "name": name, "type": 1, "options": options }])
+    }
+
+    #[test]
+    fn options_in_order() {
+        let options = json!([
+            { "name": "confirm", "type": 3, "value": "confirm" },
+            { "name": "scope", "type": 3, "value": "user <@5>" },
+        ]);
+        assert_eq!(
+            args("listen", sub("clear", options)),
+            ["clear", "user", "<@5>", "confirm"]
+        );
+        let options = json!([{ "name": "value", "type": 10, "value": 0.5 }]);
+        assert_eq!(args("listen", sub("prob", options)), ["prob", "0.5"]);
+    }
+
+    #[test]
+    fn missing_options_are_skipped() {
+        let options = json!([{ "name": "confirm", "type": 3, "value": "confirm" }]);
+        assert_eq!(args("listen", sub("clear", options)), ["clear", "confirm"]);
+        assert_eq!(args("listen", sub("clear", json!([]))), ["clear"]);
+    }
+
+    #[test]
+    fn bare_command() {
+        assert!(args("listen", sub("toggle", json!([]))).is_empty());
+        assert!(args("poem", json!([])).is_empty());
+    }
+}
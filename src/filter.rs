@@ -0,0 +1,256 @@
+//! Per-channel rules for which messages the markov chains learn from.
+
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display, Formatter},
+    sync::OnceLock,
+};
+
+use regex::{RegexSet, RegexSetBuilder};
+use serde::{Deserialize, Serialize};
+use smol_str::SmolStr;
+
+/// How many deny patterns a channel can have.
+pub const MAX_DENY_PATTERNS: usize = 32;
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+const LINK_MARKERS: &[&str] = &["http://", "https://", "www.", "discord.gg/"];
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LearnFilter {
+    pub ignore_bots: bool,
+    pub ignore_links: bool,
+    /// Minimum length of a message in characters.
+    pub min_length: usize,
+    /// Maximum length of a message in characters, `0` for no limit.
+    pub max_length: usize,
+    /// Messages matching any of these patterns are ignored.
+    deny: Vec<String>,
+    pub ignored_users: BTreeSet<SmolStr>,
+    #[serde(skip)]
+    deny_set: OnceLock<Option<RegexSet>>,
+}
+
+impl Default for LearnFilter {
+    fn default() -> Self {
+        Self {
+            ignore_bots: true,
+            ignore_links: false,
+            min_length: 0,
+            max_length: 0,
+            deny: Vec::new(),
+            ignored_users: BTreeSet::new(),
+            deny_set: OnceLock::new(),
+        }
+    }
+}
+
+/// Why a message wasn't learned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    Bot,
+    IgnoredUser,
+    Link,
+    TooShort,
+    TooLong,
+    Denied,
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rejection::Bot => "sent by a bot",
+            Rejection::IgnoredUser => "sent by an ignored user",
+            Rejection::Link => "contains a link",
+            Rejection::TooShort => "too short",
+            Rejection::TooLong => "too long",
+            Rejection::Denied => "matches a deny pattern",
+        })
+    }
+}
+
+impl LearnFilter {
+    /// Runs the message through every rule, returning the first one it fails.
+    pub fn check(&self, author: &str, author_is_bot: bool, content: &str) -> Result<(), Rejection> {
+        if self.ignore_bots && author_is_bot {
+            return Err(Rejection::Bot);
+        }
+        if self.ignored_users.contains(author) {
+            return Err(Rejection::IgnoredUser);
+        }
+        if self.ignore_links && LINK_MARKERS.iter().any(|marker| content.contains(marker)) {
+            return Err(Rejection::Link);
+        }
+        let length = content.chars().count();
+        if length < self.min_length {
+            return Err(Rejection::TooShort);
+        }
+        if self.max_length != 0 && length > self.max_length {
+            return Err(Rejection::TooLong);
+        }
+        if self.deny_set().is_some_and(|set| set.is_match(content)) {
+            return Err(Rejection::Denied);
+        }
+        Ok(())
+    }
+
+    /// Adds a deny pattern, making sure it compiles first.
+    pub fn add_deny_pattern(&mut self, pattern: &str) -> Result<(), String> {
+        if self.deny.len() >= MAX_DENY_PATTERNS {
+            return Err(format!(
+                "there can't be more than {} patterns",
+                MAX_DENY_PATTERNS
+            ));
+        }
+        build_set(&[pattern]).map_err(|err| format!("invalid pattern: {}", err))?;
+        self.deny.push(pattern.to_owned());
+        self.deny_set = OnceLock::new();
+        Ok(())
+    }
+
+    /// Removes a deny pattern, returning whether it was there.
+    pub fn remove_deny_pattern(&mut self, pattern: &str) -> bool {
+        let len = self.deny.len();
+        self.deny.retain(|p| p != pattern);
+        self.deny_set = OnceLock::new();
+        self.deny.len() != len
+    }
+
+    fn deny_set(&self) -> Option<&RegexSet> {
+        self.deny_set
+            .get_or_init(|| {
+                if self.deny.is_empty() {
+                    return None;
+                }
+                build_set(&self.deny)
+                    .map_err(|err| tracing::error!("couldnt compile deny patterns: {}", err))
+                    .ok()
+            })
+            .as_ref()
+    }
+}
+
+fn build_set<S: AsRef<str>>(patterns: &[S]) -> Result<RegexSet, regex::Error> {
+    RegexSetBuilder::new(patterns)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+}
+
+impl Display for LearnFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let on_off = |b: bool| if b { "on" } else { "off" };
+        writeln!(f, "ignore bots: {}", on_off(self.ignore_bots))?;
+        writeln!(f, "ignore links: {}", on_off(self.ignore_links))?;
+        writeln!(f, "min length: {}", self.min_length)?;
+        if self.max_length == 0 {
+            writeln!(f, "max length: none")?;
+        } else {
+            writeln!(f, "max length: {}", self.max_length)?;
+        }
+        if self.deny.is_empty() {
+            writeln!(f, "deny patterns: none")?;
+        } else {
+            writeln!(f, "deny patterns:")?;
+            for pattern in &self.deny {
+                writeln!(f, "- `{}`", pattern)?;
+            }
+        }
+        if self.ignored_users.is_empty() {
+            write!(f, "ignored users: none")
+        } else {
+            let users = self
+                .ignored_users
+                .iter()
+                .map(SmolStr::as_str)
+                .collect::<Vec<_>>();
+            write!(f, "ignored users: {}", users.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_only_ignores_bots() {
+        let filter = LearnFilter::default();
+        assert_eq!(filter.check("a", true, "beep"), Err(Rejection::Bot));
+        assert_eq!(filter.check("a", false, "beep"), Ok(()));
+        assert_eq!(filter.check("a", false, "https://example.com"), Ok(()));
+        assert_eq!(filter.check("a", false, ""), Ok(()));
+
+        let filter = LearnFilter {
+            ignore_bots: false,
+            ..LearnFilter::default()
+        };
+        assert_eq!(filter.check("a", true, "beep"), Ok(()));
+    }
+
+    #[test]
+    fn rules() {
+        let mut filter = LearnFilter {
+            ignore_links: true,
+            min_length: 3,
+            max_length: 10,
+            ..LearnFilter::default()
+        };
+        filter.ignored_users.insert("spammer".into());
+        filter.add_deny_pattern(r"(?i)\bsecret\b").unwrap();
+        filter.add_deny_pattern(r"^\d+$").unwrap();
+
+        let cases = [
+            ("a", false, "hello", Ok(())),
+            ("a", true, "hello", Err(Rejection::Bot)),
+            ("spammer", false, "hello", Err(Rejection::IgnoredUser)),
+            ("a", false, "www.a.com", Err(Rejection::Link)),
+            ("a", false, "discord.gg/x", Err(Rejection::Link)),
+            ("a", false, "hi", Err(Rejection::TooShort)),
+            // characters, not bytes
+            ("a", false, "héé", Ok(())),
+            ("a", false, "ééééééééééé", Err(Rejection::TooLong)),
+            ("a", false, "a SECRET", Err(Rejection::Denied)),
+            ("a", false, "secrets", Ok(())),
+            ("a", false, "12345", Err(Rejection::Denied)),
+            ("a", false, "12345 ok", Ok(())),
+        ];
+        for (author, is_bot, content, expected) in cases {
+            assert_eq!(
+                filter.check(author, is_bot, content),
+                expected,
+                "{:?}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn deny_patterns() {
+        let mut filter = LearnFilter::default();
+        assert!(filter.add_deny_pattern("(unclosed").is_err());
+        assert!(filter.add_deny_pattern("a{1000}{1000}").is_err());
+
+        filter.add_deny_pattern("bad").unwrap();
+        assert_eq!(filter.check("a", false, "so bad"), Err(Rejection::Denied));
+        assert!(!filter.remove_deny_pattern("missing"));
+        assert!(filter.remove_deny_pattern("bad"));
+        assert_eq!(filter.check("a", false, "so bad"), Ok(()));
+
+        for i in 0..MAX_DENY_PATTERNS {
+            filter.add_deny_pattern(&i.to_string()).unwrap();
+        }
+        assert!(filter.add_deny_pattern("one more").is_err());
+    }
+
+    #[test]
+    fn saved_patterns_are_compiled_on_load() {
+        let filter: LearnFilter = ron::from_str(r#"(deny: ["bad"], min_length: 2)"#).unwrap();
+        assert!(filter.ignore_bots);
+        assert_eq!(filter.check("a", false, "bad"), Err(Rejection::Denied));
+        assert_eq!(filter.check("a", false, "x"), Err(Rejection::TooShort));
+
+        // broken patterns from an older version don't filter everything
+        let filter: LearnFilter = ron::from_str(r#"(deny: ["(broken"])"#).unwrap();
+        assert_eq!(filter.check("a", false, "(broken"), Ok(()));
+    }
+}
@@ -1,67 +1,70 @@
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::Infallible,
     error::Error,
     fmt::{self, Debug, Display, Formatter},
-    ops::Not,
-    path::Path,
+    io,
+    ops::{Not, RangeInclusive},
     sync::Arc,
     time::Duration,
 };
 
 use async_trait::async_trait;
-use dashmap::DashMap;
-use markov::Chain;
+use chain::Chain;
+use command::{Context, Permission, Registry, Reply};
+use config::Config;
+use corpus::Corpus;
+use dashmap::{mapref::one::RefMut, DashMap};
+use filter::LearnFilter;
+use parking_lot::{Mutex, RwLock};
+use perms::{Level, PermsData};
 use rand::{
     prelude::{IteratorRandom, SmallRng},
     Rng, SeedableRng,
 };
+use ratelimit::{RateLimiter, Verdict};
+use resources::Resources;
 use serde::{Deserialize, Serialize};
 use smol_str::SmolStr;
+use storage::{Changes, Storage, StorageError};
 
+pub mod chain;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod command;
+pub mod config;
+pub mod corpus;
 #[cfg(feature = "discord")]
 pub mod discord;
+pub mod filter;
+#[cfg(feature = "matrix")]
+pub mod matrix;
+pub mod perms;
+pub mod ratelimit;
+pub mod resources;
+pub mod runtime;
+pub mod search;
+pub mod storage;
+pub mod tokenizer;
 
-pub const AUTO_SAVE_PERIOD: u64 = 60 * 60; // save every hour
-pub const PREFIX_DEF: &str = "b/";
-pub const PRESENCE_DEF: &str = "G-go for it, yay. Mii, nipah~☆";
+pub const AUTO_SAVE_RETRY_DELAY: Duration = Duration::from_secs(10);
 pub const CHANNEL_MARK_MSG: &str =
     "First set this channel for listening, dumb human.\nA tip: you can do so with `listen`.";
 pub const NOT_ENOUGH_PERMS: &str = "Foolish human, you don't have enough permissions to do this.";
+pub const NO_POEM_MSG: &str = "No poem with those words. Try again, maybe a miracle will occur.";
 
 pub const POEMS: &str = include_str!("../resources/poems.txt");
 pub const INSULTS: &str = include_str!("../resources/insults.txt");
 pub const UMAD_JPG: &[u8] = include_bytes!("../resources/umad.jpg");
 
-pub const HELP_TEXT: &str = "commands are:
-- `help`: posts this text
-- `poem`: search / get random poem
-- `gen`: generate stuff from markov chains
-- `listen`: markov chain listener management commands
-- `fuckyou`: posts funny \"u mad?\" image
+/// Poems listed on a page of `poem search`, so it fits in a message.
+const POEMS_PER_PAGE: usize = 5;
 
-use `help command` to get more information about a command";
+/// Servers listed by `owner stats`, so it fits in a message.
+const STATS_GUILDS: usize = 20;
 
-pub const GEN_HELP_TEXT: &str = "generate stuff from markov chains
-
-if called with no arguments it will generate random text using the channel's markov chain
-if called with a user id it will generate random text using the user's markov chain in this channel
-
-subcommands are:
-- `poem`: generates a random poem";
-
-pub const POEM_HELP_TEXT: &str = "search / get random poem or generate one
-
-if called with no arguments it will get a random poem
-arguments are counted as search keywords";
-
-pub const FUCKYOU_HELP_TEXT: &str = "posts funny \"u mad?\" image";
-
-pub const LISTEN_HELP_TEXT: &str = "markov chain listener management commands
-
-if called with no arguments it will toggle listen status for the current channel
-
-subcommands are:
-- `getprob`: get message posting probability value
-- `setprob <value>`: set message posting probability value. must be a percentage. calling it without any argument or invalid argument will set it to `5.0`.";
+/// Markov chain orders a channel can pick.
+pub const MARKOV_ORDERS: RangeInclusive<usize> = 1..=4;
 
 type MChain = Chain<SmolStr>;
 
@@ -76,8 +79,50 @@ pub trait Handler: Send + Sync {
         reply: bool,
     ) -> Result<SmolStr, BotError<Self::Error>>;
 
-    async fn author_has_manage_perm(&self) -> Result<bool, BotError<Self::Error>>;
+    /// Sends a reply only the author can see, on platforms that have those.
+    /// Anywhere else it's a normal reply.
+    async fn send_ephemeral(
+        &self,
+        text: &str,
+        attach: Option<(&str, Vec<u8>)>,
+    ) -> Result<SmolStr, BotError<Self::Error>> {
+        self.send_message(text, attach, true).await
+    }
+
+    /// The level the platform gives the author, from their permissions in
+    /// the server or channel.
+    async fn author_level(&self) -> Result<Level, BotError<Self::Error>>;
 
+    /// Ids of the author's roles, on platforms that have those.
+    async fn author_roles(&self) -> Result<Vec<SmolStr>, BotError<Self::Error>> {
+        Ok(Vec::new())
+    }
+
+    /// Makes the bot leave the server `guild_id`. Returns `false` if it can't
+    /// on this platform.
+    async fn leave_guild(&self, _guild_id: &str) -> Result<bool, BotError<Self::Error>> {
+        Ok(false)
+    }
+
+    /// Sends `text` to the channel `channel_id` instead of the one the message
+    /// is in. Returns `false` if it can't on this platform.
+    async fn send_to(&self, _channel_id: &str, _text: &str) -> Result<bool, BotError<Self::Error>> {
+        Ok(false)
+    }
+
+    /// Tells the platform the reply to a command is coming, but can take a
+    /// while. On platforms where that decides who can see the reply, it's
+    /// only the author if `ephemeral` is set.
+    async fn defer(&self, _ephemeral: bool) -> Result<(), BotError<Self::Error>> {
+        Ok(())
+    }
+
+    /// Name of the platform, used to namespace channel and guild ids.
+    fn platform(&self) -> &str;
+    /// Id of the bot's own user on the platform.
+    fn bot_user_id(&self) -> &str;
+    /// Whether the author of the message is a bot.
+    fn author_is_bot(&self) -> bool;
     fn referenced_id(&self) -> Option<&str>;
     fn id(&self) -> &str;
     fn author(&self) -> &str;
@@ -86,22 +131,89 @@ pub trait Handler: Send + Sync {
     fn guild_id(&self) -> Option<&str>;
 }
 
+/// Why a message or command couldn't be handled. Only `Storage` and
+/// `Platform` are the bot's fault, the rest are the author's.
 #[derive(Debug)]
-pub enum BotError<E> {
-    Handler(E),
+pub enum BotError<E = Infallible> {
+    /// The author needs at least this level.
+    Permission(Level),
+    /// Something the author referred to doesn't exist, worded to follow
+    /// "there is no", like "server here".
+    NotFound(SmolStr),
+    /// An argument doesn't make sense, and why.
+    InvalidArgument(SmolStr),
+    Storage(StorageError),
+    /// The author has to wait this long before trying again.
+    RateLimited(Duration),
+    /// The platform failed, like a message that couldn't be sent.
+    Platform(E),
+}
+
+impl BotError {
+    /// The same error, coming from a command that can't fail on the platform.
+    pub fn cast<E>(self) -> BotError<E> {
+        match self {
+            BotError::Permission(level) => BotError::Permission(level),
+            BotError::NotFound(what) => BotError::NotFound(what),
+            BotError::InvalidArgument(why) => BotError::InvalidArgument(why),
+            BotError::Storage(err) => BotError::Storage(err),
+            BotError::RateLimited(wait) => BotError::RateLimited(wait),
+            BotError::Platform(never) => match never {},
+        }
+    }
+}
+
+impl<E> BotError<E> {
+    /// Whether it's the bot's fault, which is worth logging as an error.
+    pub fn is_internal(&self) -> bool {
+        matches!(self, BotError::Storage(_) | BotError::Platform(_))
+    }
+
+    /// What the bot tells the author, without the details.
+    pub fn reply(&self) -> SmolStr {
+        match self {
+            BotError::Permission(_) => NOT_ENOUGH_PERMS.into(),
+            BotError::NotFound(what) => format!(
+                "I looked everywhere, there is no {}. Stop making things up.",
+                what
+            )
+            .into(),
+            BotError::InvalidArgument(why) => format!("{}. Try again, dumb human.", why).into(),
+            BotError::Storage(_) => {
+                "My memory failed me. Don't get used to it, it won't happen again.".into()
+            }
+            BotError::RateLimited(wait) => {
+                let secs = wait.as_secs_f64().ceil() as u64;
+                let plural = if secs == 1 { "" } else { "s" };
+                format!(
+                    "Slow down, human. I'll listen to you again in {} second{}.",
+                    secs, plural
+                )
+                .into()
+            }
+            BotError::Platform(_) => {
+                "Something went wrong, and it's not my fault. Blame the platform.".into()
+            }
+        }
+    }
 }
 
 impl<E: Display> Display for BotError<E> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            BotError::Handler(err) => write!(f, "error occured in handler: {}", err),
+            BotError::Permission(level) => write!(f, "needs at least {}", level),
+            BotError::NotFound(what) => write!(f, "there is no {}", what),
+            BotError::InvalidArgument(why) => write!(f, "invalid argument: {}", why),
+            BotError::Storage(err) => write!(f, "error occured in storage: {}", err),
+            BotError::RateLimited(wait) => write!(f, "rate limited for {:?}", wait),
+            BotError::Platform(err) => write!(f, "error occured in handler: {}", err),
         }
     }
 }
 
 impl<E> From<E> for BotError<E> {
     fn from(err: E) -> Self {
-        BotError::Handler(err)
+        BotError::Platform(err)
     }
 }
 
@@ -113,8 +225,29 @@ pub struct MarkovData {
     chain: MChain,
     #[serde(default)]
     per_user: DashMap<SmolStr, MChain>,
+    /// Whether messages sent in the channel are fed to the chains.
     #[serde(default)]
-    enabled: bool,
+    learn: bool,
+    /// Whether the bot randomly posts generated messages in the channel.
+    #[serde(default)]
+    speak: bool,
+    /// Order of `chain`.
+    #[serde(default = "default_markov_order")]
+    order: usize,
+    /// Chains of the other orders used in this channel, which are fed
+    /// alongside `chain` so switching back to them doesn't lose anything.
+    #[serde(default)]
+    other_chains: HashMap<usize, MChain>,
+    /// Namespaced id of the guild the channel is in, if it's in one.
+    #[serde(default)]
+    guild_id: Option<SmolStr>,
+    /// Decides which messages are learned.
+    #[serde(default)]
+    filter: LearnFilter,
+    /// Whether every message the chains learned is in the corpus, so they can
+    /// be rebuilt without losing anything.
+    #[serde(default)]
+    fully_logged: bool,
 }
 
 impl Default for MarkovData {
@@ -123,11 +256,130 @@ impl Default for MarkovData {
             probability: 5.0,
             chain: MChain::new(),
             per_user: DashMap::new(),
-            enabled: false,
+            learn: false,
+            speak: false,
+            order: default_markov_order(),
+            other_chains: HashMap::new(),
+            guild_id: None,
+            filter: LearnFilter::default(),
+            fully_logged: false,
+        }
+    }
+}
+
+impl MarkovData {
+    /// Feeds the message to the chains if it passes the filter, returning
+    /// the tokens that were fed.
+    fn learn(&mut self, author: &str, author_is_bot: bool, content: &str) -> Option<Vec<SmolStr>> {
+        match self.filter.check(author, author_is_bot, content) {
+            Ok(()) => {
+                let tokens = tokenizer::tokenize(content);
+                self.feed(author, &tokens);
+                Some(tokens)
+            }
+            Err(reason) => {
+                tracing::debug!("not learning message: {}", reason);
+                None
+            }
+        }
+    }
+
+    fn feed(&mut self, author: &str, tokens: &[SmolStr]) {
+        self.chain.feed(tokens);
+        for chain in self.other_chains.values_mut() {
+            chain.feed(tokens);
+        }
+        self.per_user.entry(author.into()).or_default().feed(tokens);
+    }
+
+    /// Switches `chain` to one of `order`, keeping the current one around.
+    /// Returns whether a chain of that order existed already.
+    fn set_order(&mut self, order: usize) -> bool {
+        if order == self.order {
+            return true;
+        }
+        let existed = self.other_chains.contains_key(&order);
+        let chain = self
+            .other_chains
+            .remove(&order)
+            .unwrap_or_else(|| MChain::of_order(order));
+        let old = std::mem::replace(&mut self.chain, chain);
+        self.other_chains.insert(self.order, old);
+        self.order = order;
+        existed
+    }
+
+    /// Empties every chain, keeping the settings.
+    fn clear_chains(&mut self) {
+        self.chain = MChain::of_order(self.order);
+        for (order, chain) in self.other_chains.iter_mut() {
+            *chain = MChain::of_order(*order);
+        }
+        self.per_user.clear();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+            && self.other_chains.values().all(MChain::is_empty)
+            && self.per_user.is_empty()
+    }
+}
+
+/// Result of [`Bot::forget_author`].
+struct Forgotten {
+    /// Channels something was forgotten in.
+    channels: usize,
+    /// Channels whose chains couldn't be rebuilt without the author.
+    tangled: usize,
+}
+
+impl Forgotten {
+    fn tangled_note(&self) -> String {
+        if self.tangled == 0 {
+            String::new()
+        } else {
+            format!(
+                ", but it's still mixed into the chains of {} of them since they learned it before a corpus was kept. clear them to get rid of it.",
+                self.tangled
+            )
         }
     }
 }
 
+enum ClearScope<'a> {
+    Channel,
+    Guild(&'a str),
+    User(SmolStr),
+}
+
+/// Accepts channel mentions (`<#id>`) as well as plain ids.
+fn parse_channel_id(arg: &str) -> &str {
+    arg.strip_prefix("<#")
+        .and_then(|id| id.strip_suffix('>'))
+        .unwrap_or(arg)
+}
+
+/// Accepts mentions (`<@id>` or `<@!id>`) as well as plain ids.
+fn parse_user_id(arg: &str) -> SmolStr {
+    arg.strip_prefix("<@")
+        .and_then(|id| id.strip_suffix('>'))
+        .map_or(arg, |id| id.trim_start_matches('!'))
+        .into()
+}
+
+fn default_markov_order() -> usize {
+    1
+}
+
+/// Chain of a whole guild, fed whatever its channels learn.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct GuildData {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default)]
+    chain: MChain,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct InsultData {
     #[serde(default)]
@@ -148,94 +400,563 @@ impl Default for InsultData {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct BotData {
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct UserData {
+    /// Whether the user asked not to be learned from.
     #[serde(default)]
-    user_id: SmolStr,
+    opted_out: bool,
+}
+
+/// Everything the bot remembers, persisted through a [`Storage`].
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BotData {
     #[serde(default)]
     insult_data: DashMap<SmolStr, InsultData>,
     #[serde(default)]
     mchain: DashMap<SmolStr, MarkovData>,
     #[serde(default)]
     prefix: DashMap<SmolStr, SmolStr>,
+    /// Keyed by namespaced user id.
+    #[serde(default)]
+    users: DashMap<SmolStr, UserData>,
+    /// Keyed by namespaced guild id.
+    #[serde(default)]
+    guilds: DashMap<SmolStr, GuildData>,
+    /// Keyed like `prefix`.
+    #[serde(default)]
+    perms: DashMap<SmolStr, PermsData>,
+    #[serde(skip)]
+    changes: Mutex<Changes>,
+}
+
+impl BotData {
+    fn markov_mut(&self, channel_id: &str) -> Option<RefMut<'_, SmolStr, MarkovData>> {
+        let data = self.mchain.get_mut(channel_id)?;
+        self.changes.lock().mchain.insert(channel_id.into());
+        Some(data)
+    }
+
+    fn markov_entry(&self, channel_id: &str) -> RefMut<'_, SmolStr, MarkovData> {
+        self.changes.lock().mchain.insert(channel_id.into());
+        self.mchain.entry(channel_id.into()).or_default()
+    }
+
+    fn insult_entry(&self, channel_id: &str) -> RefMut<'_, SmolStr, InsultData> {
+        self.changes.lock().insult_data.insert(channel_id.into());
+        self.insult_data.entry(channel_id.into()).or_default()
+    }
+
+    /// Like [`Self::insult_entry`], but doesn't mark it changed, for the
+    /// running count and the last insult, which aren't worth a save of their
+    /// own.
+    fn insult_state(&self, channel_id: &str) -> RefMut<'_, SmolStr, InsultData> {
+        self.insult_data.entry(channel_id.into()).or_default()
+    }
+
+    fn user_entry(&self, user_id: &str) -> RefMut<'_, SmolStr, UserData> {
+        self.changes.lock().users.insert(user_id.into());
+        self.users.entry(user_id.into()).or_default()
+    }
+
+    fn guild_mut(&self, guild_id: &str) -> Option<RefMut<'_, SmolStr, GuildData>> {
+        let data = self.guilds.get_mut(guild_id)?;
+        self.changes.lock().guilds.insert(guild_id.into());
+        Some(data)
+    }
+
+    fn guild_entry(&self, guild_id: &str) -> RefMut<'_, SmolStr, GuildData> {
+        self.changes.lock().guilds.insert(guild_id.into());
+        self.guilds.entry(guild_id.into()).or_default()
+    }
+
+    fn perms_entry(&self, context_id: &str) -> RefMut<'_, SmolStr, PermsData> {
+        self.changes.lock().perms.insert(context_id.into());
+        self.perms.entry(context_id.into()).or_default()
+    }
+
+    fn set_prefix(&self, context_id: &str, prefix: &str) {
+        self.changes.lock().prefix.insert(context_id.into());
+        self.prefix.insert(context_id.into(), prefix.into());
+    }
+
+    /// Marks every entry as changed, so the next save writes all of them.
+    fn mark_all_changed(&self) {
+        let mut changes = self.changes.lock();
+        changes
+            .mchain
+            .extend(self.mchain.iter().map(|e| e.key().clone()));
+        changes
+            .insult_data
+            .extend(self.insult_data.iter().map(|e| e.key().clone()));
+        changes
+            .prefix
+            .extend(self.prefix.iter().map(|e| e.key().clone()));
+        changes
+            .users
+            .extend(self.users.iter().map(|e| e.key().clone()));
+        changes
+            .guilds
+            .extend(self.guilds.iter().map(|e| e.key().clone()));
+        changes
+            .perms
+            .extend(self.perms.iter().map(|e| e.key().clone()));
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Bot {
     data: Arc<BotData>,
-    poem_chain: Arc<MChain>,
+    resources: Arc<RwLock<Arc<Resources>>>,
+    storage: Arc<dyn Storage>,
+    config: Arc<Config>,
+    corpus: Option<Arc<Corpus>>,
+    /// Everything random the bot does goes through this, so it can be seeded.
+    rng: Arc<Mutex<SmallRng>>,
+    commands: Arc<Registry>,
+    rate_limiter: Arc<RateLimiter>,
 }
 
 impl Bot {
-    pub fn new(user_id: SmolStr) -> Self {
+    pub fn new(storage: Arc<dyn Storage>, config: Arc<Config>) -> Self {
+        Self::with_data(BotData::default(), storage, config)
+    }
+
+    fn with_data(data: BotData, storage: Arc<dyn Storage>, config: Arc<Config>) -> Self {
         Self {
-            data: Arc::new(BotData {
-                user_id,
-                insult_data: DashMap::new(),
-                mchain: DashMap::new(),
-                prefix: DashMap::new(),
-            }),
-            poem_chain: default_poem_chain().into(),
+            data: Arc::new(data),
+            resources: Arc::new(RwLock::new(Arc::new(load_resources(&config)))),
+            storage,
+            corpus: config
+                .corpus_dir
+                .as_ref()
+                .map(|dir| Arc::new(Corpus::new(dir))),
+            rng: Arc::new(Mutex::new(match config.rng_seed {
+                Some(seed) => SmallRng::seed_from_u64(seed),
+                None => SmallRng::from_entropy(),
+            })),
+            commands: Arc::new(Registry::default()),
+            rate_limiter: Arc::new(RateLimiter::default()),
+            config,
         }
     }
 
-    pub async fn read_from(data_path: impl AsRef<Path>) -> Result<Self, std::io::Error> {
-        let compressed = tokio::fs::read(data_path).await?;
-        let raw = lz4_flex::decompress_size_prepended(&compressed).unwrap();
-        let data = ron::de::from_bytes(&raw).expect("failed to parse data");
+    /// Reseeds the RNG, making everything the bot does from now on reproducible.
+    pub fn seed_rng(&self, seed: u64) {
+        *self.rng.lock() = SmallRng::seed_from_u64(seed);
+    }
 
-        Ok(Self {
-            data: Arc::new(data),
-            poem_chain: default_poem_chain().into(),
-        })
+    /// How long to "type" before sending a message.
+    pub fn typing_delay(&self) -> Duration {
+        self.config.typing_delay(&mut *self.rng.lock())
+    }
+
+    /// Loads the bot from `storage`, or returns `None` if nothing was saved there yet.
+    pub async fn load(
+        storage: Arc<dyn Storage>,
+        config: Arc<Config>,
+    ) -> Result<Option<Self>, StorageError> {
+        let data = storage.load().await?;
+        Ok(data.map(|data| Self::with_data(data, storage, config)))
+    }
+
+    pub fn commands(&self) -> &Registry {
+        &self.commands
+    }
+
+    pub fn config(&self) -> &Arc<Config> {
+        &self.config
     }
 
-    pub fn start_autosave_task(&self, data_path: impl AsRef<Path>) {
+    pub fn resources(&self) -> Arc<Resources> {
+        self.resources.read().clone()
+    }
+
+    /// Reads the resources in the configured directory again. Returns `None`
+    /// if there is none, in which case the built in ones are kept.
+    pub fn reload_resources(&self) -> Option<io::Result<Arc<Resources>>> {
+        let dir = self.config.resources_dir.as_ref()?;
+        Some(Resources::load(dir).map(|resources| {
+            let resources = Arc::new(resources);
+            *self.resources.write() = resources.clone();
+            resources
+        }))
+    }
+
+    /// Saves every `period`. Failed saves are retried sooner, with the delay
+    /// doubling after every failure until it's back to `period`.
+    pub fn start_autosave_task(&self, period: Duration) {
         let bot = self.clone();
-        let data_path = data_path.as_ref().to_owned();
         tokio::spawn(async move {
+            let mut delay = period;
+            let mut failures = 0;
             loop {
-                if let Err(err) = bot.save_to(&data_path).await {
-                    tracing::error!("couldnt save bot data: {}", err);
-                    break;
+                tokio::time::sleep(delay).await;
+                match bot.save().await {
+                    Ok(()) => {
+                        failures = 0;
+                        delay = period;
+                    }
+                    Err(err) => {
+                        failures += 1;
+                        delay =
+                            (AUTO_SAVE_RETRY_DELAY * 2_u32.pow(failures.min(16) - 1)).min(period);
+                        tracing::error!(
+                            "couldnt save bot data ({} failures in a row), retrying in {:?}: {}",
+                            failures,
+                            delay,
+                            err
+                        );
+                    }
                 }
-                tokio::time::sleep(std::time::Duration::from_secs(AUTO_SAVE_PERIOD)).await;
             }
         });
     }
 
-    pub async fn save_to(&self, data_path: impl AsRef<Path>) -> Result<(), std::io::Error> {
-        tokio::fs::write(
-            data_path,
-            lz4_flex::compress_prepend_size(
-                ron::ser::to_string(&self.data)
-                    .expect("couldnt serialize")
-                    .as_bytes(),
-            ),
-        )
-        .await
+    /// Saves everything that changed since the last save.
+    pub async fn save(&self) -> Result<(), StorageError> {
+        let changes = std::mem::take(&mut *self.data.changes.lock());
+        if changes.is_empty() {
+            return Ok(());
+        }
+        if let Err(err) = self.storage.save(&self.data, &changes).await {
+            // keep them around so the next save tries again
+            self.data.changes.lock().extend(changes);
+            return Err(err);
+        }
+        Ok(())
     }
 
-    pub fn markov_toggle_mark_channel(&self, channel_id: &str) -> SmolStr {
-        let mut m = self.data.mchain.entry(channel_id.into()).or_default();
-        m.enabled = m.enabled.not();
-        if m.enabled {
+    /// Counts of everything the bot knows about, on every platform.
+    pub fn owner_stats(&self) -> SmolStr {
+        use std::fmt::Write as _;
+
+        let (mut learning, mut speaking) = (0, 0);
+        let mut platforms = BTreeMap::<SmolStr, usize>::new();
+        let mut guilds = BTreeMap::<SmolStr, usize>::new();
+        for entry in self.data.mchain.iter() {
+            learning += entry.learn as usize;
+            speaking += entry.speak as usize;
+            let platform = entry
+                .key()
+                .split_once(':')
+                .map_or("", |(platform, _)| platform);
+            *platforms.entry(platform.into()).or_default() += 1;
+            if let Some(guild_id) = &entry.guild_id {
+                *guilds.entry(guild_id.clone()).or_default() += 1;
+            }
+        }
+        for entry in self.data.guilds.iter() {
+            guilds.entry(entry.key().clone()).or_default();
+        }
+        let guild_chains = self.data.guilds.iter().filter(|e| e.enabled).count();
+        let opted_out = self.data.users.iter().filter(|e| e.opted_out).count();
+
+        let mut text = format!(
+            "channels: {} ({} learning, {} speaking)\nservers: {} ({} with a server chain)\nusers who opted out: {}",
+            self.data.mchain.len(),
+            learning,
+            speaking,
+            guilds.len(),
+            guild_chains,
+            opted_out
+        );
+        for (platform, channels) in &platforms {
+            let _ = write!(text, "\n- `{}`: {} channels", platform, channels);
+        }
+        if !guilds.is_empty() {
+            text.push_str("\nservers:");
+        }
+        for (guild_id, channels) in guilds.iter().take(STATS_GUILDS) {
+            let _ = write!(text, "\n- `{}`: {} channels", guild_id, channels);
+        }
+        if guilds.len() > STATS_GUILDS {
+            let _ = write!(text, "\nand {} more", guilds.len() - STATS_GUILDS);
+        }
+        text.into()
+    }
+
+    /// Channels of `platform` the bot speaks in, not namespaced.
+    fn speaking_channels(&self, platform: &str) -> Vec<SmolStr> {
+        let prefix = namespaced(platform, "");
+        self.data
+            .mchain
+            .iter()
+            .filter(|e| e.speak)
+            .filter_map(|e| e.key().strip_prefix(prefix.as_str()).map(Into::into))
+            .collect()
+    }
+
+    pub fn markov_toggle_mark_channel(&self, channel_id: &str, guild_id: Option<&str>) -> SmolStr {
+        let mut m = self.markov_entry(channel_id, guild_id);
+        let marked = !(m.learn || m.speak);
+        m.learn = marked;
+        m.speak = marked;
+        if marked {
             SmolStr::new_inline("marked channel")
         } else {
             SmolStr::new_inline("unmarked channel")
         }
     }
 
+    pub fn markov_toggle_learn(&self, channel_id: &str, guild_id: Option<&str>) -> SmolStr {
+        let mut m = self.markov_entry(channel_id, guild_id);
+        m.learn = m.learn.not();
+        if m.learn {
+            "learning from this channel".into()
+        } else {
+            "stopped learning from this channel".into()
+        }
+    }
+
+    pub fn markov_toggle_speak(&self, channel_id: &str, guild_id: Option<&str>) -> SmolStr {
+        let mut m = self.markov_entry(channel_id, guild_id);
+        m.speak = m.speak.not();
+        if m.speak {
+            "speaking in this channel".into()
+        } else {
+            "stopped speaking in this channel".into()
+        }
+    }
+
+    fn markov_entry(
+        &self,
+        channel_id: &str,
+        guild_id: Option<&str>,
+    ) -> RefMut<'_, SmolStr, MarkovData> {
+        let mut m = self.data.markov_entry(channel_id);
+        if let Some(guild_id) = guild_id {
+            m.guild_id = Some(guild_id.into());
+        }
+        m
+    }
+
     pub fn markov_set_prob(&self, channel_id: &str, new_prob: &str) -> SmolStr {
-        let prob = new_prob.parse().unwrap_or(5).min(100).max(0);
-        if let Some(mut data) = self.data.mchain.get_mut(channel_id) {
-            data.probability = prob as f64;
+        let prob = new_prob
+            .parse::<f64>()
+            .ok()
+            .filter(|prob| prob.is_finite())
+            .map_or(5.0, |prob| prob.clamp(0.0, 100.0));
+        if let Some(mut data) = self.data.markov_mut(channel_id) {
+            data.probability = prob;
             format!("Set probability to {}%", prob).into()
         } else {
             CHANNEL_MARK_MSG.into()
         }
     }
 
+    pub fn markov_set_order(&self, channel_id: &str, new_order: &str) -> SmolStr {
+        let order = match new_order.parse() {
+            Ok(order) if MARKOV_ORDERS.contains(&order) => order,
+            _ => {
+                return format!(
+                    "order must be between {} and {}",
+                    MARKOV_ORDERS.start(),
+                    MARKOV_ORDERS.end()
+                )
+                .into()
+            }
+        };
+        if let Some(mut data) = self.data.markov_mut(channel_id) {
+            if data.set_order(order) {
+                format!("Set order to {}", order).into()
+            } else {
+                format!(
+                    "Set order to {}, it will need new messages before it can say anything",
+                    order
+                )
+                .into()
+            }
+        } else {
+            CHANNEL_MARK_MSG.into()
+        }
+    }
+
+    /// Rebuilds the chains of `channel_id` from its corpus, returning how many
+    /// messages were in it. `None` if no corpus is kept or the channel isn't
+    /// listened to.
+    pub async fn markov_rebuild(&self, channel_id: &str) -> io::Result<Option<usize>> {
+        let corpus = match &self.corpus {
+            Some(corpus) => corpus,
+            None => return Ok(None),
+        };
+        if !self.data.mchain.contains_key(channel_id) {
+            return Ok(None);
+        }
+        let entries = corpus.read(channel_id).await?;
+        Ok(self.data.markov_mut(channel_id).map(|mut data| {
+            data.clear_chains();
+            for entry in &entries {
+                data.learn(&entry.author, entry.author_is_bot, &entry.content);
+            }
+            data.fully_logged = true;
+            entries.len()
+        }))
+    }
+
+    pub async fn markov_toggle_guild(&self, guild_id: &str) -> SmolStr {
+        let enabled = {
+            let mut guild = self.data.guild_entry(guild_id);
+            guild.enabled = !guild.enabled;
+            if !guild.enabled {
+                guild.chain = MChain::new();
+            }
+            guild.enabled
+        };
+        if !enabled {
+            return "stopped learning for the whole server".into();
+        }
+        match self.guild_rebuild(guild_id).await {
+            Ok(Some(count)) => format!(
+                "learning for the whole server, starting with {} messages learned so far",
+                count
+            )
+            .into(),
+            Ok(None) => "learning for the whole server from now on".into(),
+            Err(err) => {
+                tracing::error!("couldnt rebuild `{}`: {}", guild_id, err);
+                "learning for the whole server, but couldnt read what was recorded so far".into()
+            }
+        }
+    }
+
+    /// Rebuilds the chain of `guild_id` from the corpus of every channel in
+    /// it that learns, returning how many messages it learned. Does nothing
+    /// if the guild has no chain or no corpus is kept.
+    async fn guild_rebuild(&self, guild_id: &str) -> io::Result<Option<usize>> {
+        let corpus = match &self.corpus {
+            Some(corpus) => corpus,
+            None => return Ok(None),
+        };
+        if !self
+            .data
+            .guilds
+            .get(guild_id)
+            .is_some_and(|guild| guild.enabled)
+        {
+            return Ok(None);
+        }
+        let channels = self
+            .data
+            .mchain
+            .iter()
+            .filter(|e| e.learn && e.guild_id.as_deref() == Some(guild_id))
+            .map(|e| e.key().clone())
+            .collect::<Vec<_>>();
+
+        let mut chain = MChain::new();
+        let mut count = 0;
+        for channel_id in &channels {
+            let entries = corpus.read(channel_id).await?;
+            let mlisten = match self.data.mchain.get(channel_id) {
+                Some(mlisten) => mlisten,
+                None => continue,
+            };
+            for entry in &entries {
+                if mlisten
+                    .filter
+                    .check(&entry.author, entry.author_is_bot, &entry.content)
+                    .is_ok()
+                {
+                    chain.feed(tokenizer::tokenize(&entry.content));
+                    count += 1;
+                }
+            }
+        }
+        if let Some(mut guild) = self.data.guild_mut(guild_id) {
+            guild.chain = chain;
+        }
+        Ok(Some(count))
+    }
+
+    /// Rebuilds the chains of the guilds the channels are in, after they were
+    /// rebuilt or something was removed from them.
+    pub async fn guilds_rebuild(&self, channels: impl IntoIterator<Item = &SmolStr>) {
+        let guilds = channels
+            .into_iter()
+            .filter_map(|channel_id| self.data.mchain.get(channel_id)?.guild_id.clone())
+            .collect::<HashSet<_>>();
+        for guild_id in &guilds {
+            if let Err(err) = self.guild_rebuild(guild_id).await {
+                tracing::error!("couldnt rebuild `{}`: {}", guild_id, err);
+            }
+        }
+    }
+
+    /// Ids of every channel that has markov data.
+    pub fn markov_channels(&self) -> Vec<SmolStr> {
+        self.data.mchain.iter().map(|e| e.key().clone()).collect()
+    }
+
+    pub fn markov_get_filter(&self, channel_id: &str) -> SmolStr {
+        if let Some(data) = self.data.mchain.get(channel_id) {
+            data.filter.to_string().into()
+        } else {
+            CHANNEL_MARK_MSG.into()
+        }
+    }
+
+    pub fn markov_set_filter(&self, channel_id: &str, setting: &str, value: &str) -> SmolStr {
+        let mut data = match self.data.markov_mut(channel_id) {
+            Some(data) => data,
+            None => return CHANNEL_MARK_MSG.into(),
+        };
+        let filter = &mut data.filter;
+        let on_off = |value: &str| match value {
+            "on" => Some(true),
+            "off" => Some(false),
+            _ => None,
+        };
+        let res = match setting {
+            "bots" => on_off(value)
+                .map(|on| filter.ignore_bots = on)
+                .ok_or("expected `on` or `off`".into()),
+            "links" => on_off(value)
+                .map(|on| filter.ignore_links = on)
+                .ok_or("expected `on` or `off`".into()),
+            "min" => value
+                .parse()
+                .map(|len| filter.min_length = len)
+                .map_err(|_| "expected a number".into()),
+            "max" => value
+                .parse()
+                .map(|len| filter.max_length = len)
+                .map_err(|_| "expected a number".into()),
+            "deny" if !value.is_empty() => filter.add_deny_pattern(value),
+            "allow" => {
+                if filter.remove_deny_pattern(value) {
+                    Ok(())
+                } else {
+                    Err("there is no such pattern".into())
+                }
+            }
+            "ignore" if !value.is_empty() => {
+                filter.ignored_users.insert(parse_user_id(value));
+                Ok(())
+            }
+            "unignore" => {
+                if filter.ignored_users.remove(&parse_user_id(value)) {
+                    Ok(())
+                } else {
+                    Err("that user isn't ignored".into())
+                }
+            }
+            "deny" | "ignore" => Err("no value".into()),
+            setting => Err(format!("`{}` isn't a filter setting", setting)),
+        };
+        match res {
+            Ok(()) => format!("updated filter.\n{}", filter).into(),
+            Err(err) => err.into(),
+        }
+    }
+
+    pub fn markov_get_order(&self, channel_id: &str) -> SmolStr {
+        if let Some(data) = self.data.mchain.get(channel_id) {
+            format!("Order is {}", data.order).into()
+        } else {
+            CHANNEL_MARK_MSG.into()
+        }
+    }
+
     pub fn markov_get_prob(&self, channel_id: &str) -> SmolStr {
         if let Some(data) = self.data.mchain.get(channel_id) {
             format!("Probability is {}%", data.probability).into()
@@ -248,190 +969,57 @@ impl Bot {
         &self,
         handler: &dyn Handler<Error = E>,
     ) -> Result<(), BotError<E>> {
-        let context_id = handler.guild_id().unwrap_or_else(|| handler.channel_id());
+        let channel_id = namespaced(handler.platform(), handler.channel_id());
+        let guild_id = handler
+            .guild_id()
+            .map(|id| namespaced(handler.platform(), id));
+        let context_id = guild_id.clone().unwrap_or_else(|| channel_id.clone());
+        let (channel_id, context_id) = (channel_id.as_str(), context_id.as_str());
         let prefix = self
             .data
             .prefix
             .get(context_id)
-            .map_or(PREFIX_DEF.into(), |a| a.clone());
-        #[allow(clippy::blocks_in_if_conditions)]
+            .map_or_else(|| self.config.prefix.clone(), |a| a.clone());
         if let Some(args) = handler.content().strip_prefix(prefix.as_str()) {
-            let mut args = args.split_whitespace();
-            if let Some(cmd) = args.next() {
-                match cmd {
-                    "help" => {
-                        let mut insulted = false;
-                        let text = if let Some(subcmd) = args.next() {
-                            match subcmd {
-                                "poem" => POEM_HELP_TEXT.into(),
-                                "listen" => LISTEN_HELP_TEXT.into(),
-                                "fuckyou" => FUCKYOU_HELP_TEXT.into(),
-                                "gen" => GEN_HELP_TEXT.into(),
-                                cmd => {
-                                    insulted = true;
-                                    self.unrecognised_command(cmd)
-                                }
-                            }
-                        } else {
-                            HELP_TEXT.into()
-                        };
-                        let id = handler.send_message(&text, None, true).await?;
-                        if insulted {
-                            self.insult(handler.channel_id(), id);
-                        }
-                    }
-                    "set" => {
-                        let mut insulted = false;
-                        let text = if handler.author_has_manage_perm().await? {
-                            if let Some(subcmd) = args.next() {
-                                match subcmd {
-                                    "prefix" => {
-                                        if let Some(value) = args.next() {
-                                            self.data
-                                                .prefix
-                                                .insert(context_id.into(), value.into());
-                                            format!("prefix is now `{}`.", value).into()
-                                        } else {
-                                            SmolStr::new_inline("no value")
-                                        }
-                                    }
-                                    "insult" => {
-                                        let mut m = self.insult_entry(context_id);
-                                        if m.enabled {
-                                            m.enabled = false;
-                                            SmolStr::new_inline("turned off insults")
-                                        } else {
-                                            m.enabled = true;
-                                            SmolStr::new_inline("turned on insults")
-                                        }
-                                    }
-                                    cmd => {
-                                        insulted = true;
-                                        self.unrecognised_command(cmd)
-                                    }
-                                }
-                            } else {
-                                insulted = true;
-                                self.unrecognised_command(cmd)
-                            }
-                        } else {
-                            SmolStr::new_inline("not enough permissions")
-                        };
-
-                        let id = handler.send_message(&text, None, true).await?;
-                        if insulted {
-                            self.insult(handler.channel_id(), id);
-                        }
-                    }
-                    "poem" => {
-                        let text = self.process_poem_command(
-                            args.map(|c| {
-                                let mut s = c.to_owned();
-                                s.push(' ');
-                                s
-                            })
-                            .collect::<String>()
-                            .trim_end(),
-                        );
-                        handler.send_message(&text, None, true).await?;
-                    }
-                    "fuckyou" => {
-                        handler
-                            .send_message("", Some(("umad.jpg", UMAD_JPG.to_vec())), true)
-                            .await?;
-                    }
-                    "gen" => {
-                        let text = if let Some(subcmd) = args.next() {
-                            match subcmd {
-                                "poem" => self.generate_poem(),
-                                "token" => {
-                                    if let Some(token) = args.next() {
-                                        self.gen_message(handler.channel_id(), Some(token.into()))
-                                    } else {
-                                        SmolStr::new_inline("put a token")
-                                    }
-                                }
-                                user => self.gen_user_message(handler.channel_id(), user),
-                            }
-                        } else {
-                            self.gen_message(handler.channel_id(), None)
-                        };
-                        handler.send_message(&text, None, true).await?;
-                    }
-                    "listen" => {
-                        let mut insulted = false;
-                        let text = if let Some(subcmd) = args.next() {
-                            match subcmd {
-                                "prob" => {
-                                    if let Some(new_prob) = args.next() {
-                                        if handler.author_has_manage_perm().await? {
-                                            self.markov_set_prob(handler.channel_id(), new_prob)
-                                        } else {
-                                            NOT_ENOUGH_PERMS.into()
-                                        }
-                                    } else {
-                                        self.markov_get_prob(handler.channel_id())
-                                    }
-                                }
-                                "clear" => {
-                                    if handler.author_has_manage_perm().await? {
-                                        self.data.mchain.remove(context_id);
-                                        SmolStr::new_inline("cleared data")
-                                    } else {
-                                        NOT_ENOUGH_PERMS.into()
-                                    }
-                                }
-                                cmd => {
-                                    insulted = true;
-                                    self.unrecognised_command(cmd)
-                                }
-                            }
-                        } else if handler.author_has_manage_perm().await? {
-                            self.markov_toggle_mark_channel(handler.channel_id())
-                        } else {
-                            NOT_ENOUGH_PERMS.into()
-                        };
-                        let id = handler.send_message(&text, None, true).await?;
-                        if insulted {
-                            self.insult(handler.channel_id(), id);
-                        }
-                    }
-                    cmd => {
-                        let id = handler
-                            .send_message(&self.unrecognised_command(cmd), None, true)
-                            .await?;
-                        self.insult(handler.channel_id(), id);
-                    }
+            let args = args.split_whitespace().collect::<Vec<_>>();
+            let (name, args) = match args.split_first() {
+                Some(split) => split,
+                None => {
+                    handler
+                        .send_message("What do you want?", None, true)
+                        .await?;
+                    return Ok(());
                 }
-            } else {
-                handler
-                    .send_message("What do you want?", None, true)
-                    .await?;
-            }
-        } else if self.data.user_id != handler.author() {
-            let markov = self.markov_try_gen_message(
-                handler.channel_id(),
-                handler.content(),
-                handler.author(),
-            );
-            if handler.referenced_id().map_or(false, |message_id| {
-                self.has_insult_response(handler.channel_id(), message_id, handler.content())
+            };
+            self.run_command(handler, name, args).await?;
+        } else if handler.bot_user_id() != handler.author() {
+            self.markov_learn(channel_id, handler).await;
+            let markov = self.markov_try_gen_message(channel_id, handler.content());
+            let guild_id = guild_id.as_deref();
+            if handler.referenced_id().is_some_and(|message_id| {
+                self.has_insult_response(channel_id, message_id, handler.content())
             }) {
-                handler
-                    .send_message("", Some(("umad.jpg", UMAD_JPG.to_vec())), true)
-                    .await?;
-            } else if let Some(text) = self.try_insult(handler.channel_id()) {
-                let id = handler.send_message(&text, None, true).await?;
-                self.insult(handler.channel_id(), id);
+                if self.limit_message(channel_id, guild_id) {
+                    handler
+                        .send_message("", Some(("umad.jpg", UMAD_JPG.to_vec())), true)
+                        .await?;
+                }
+            } else if let Some(text) = self.try_insult(channel_id) {
+                if self.limit_message(channel_id, guild_id) {
+                    let id = handler.send_message(&text, None, true).await?;
+                    self.insult(channel_id, id);
+                }
             } else if let Some((text, is_reply)) = markov {
-                let mut rng = rand::rngs::SmallRng::from_entropy();
+                if !self.limit_message(channel_id, guild_id) {
+                    return Ok(());
+                }
                 handler.send_message(&text, None, is_reply).await?;
-                while let Some((text, is_reply)) = self.markov_try_gen_message(
-                    handler.channel_id(),
-                    handler.content(),
-                    handler.author(),
-                ) {
-                    if rng.gen_bool(1.0 / 10.0) {
+                while let Some((text, is_reply)) =
+                    self.markov_try_gen_message(channel_id, handler.content())
+                {
+                    if self.rng.lock().gen_bool(1.0 / 10.0)
+                        || !self.limit_message(channel_id, guild_id)
+                    {
                         break;
                     }
                     tokio::time::sleep(Duration::from_millis(500)).await;
@@ -442,20 +1030,139 @@ impl Bot {
         Ok(())
     }
 
-    pub fn insult_entry(
+    /// Runs the command called `name` with `args` and sends its reply. Replies
+    /// to commands that need more than the user level are ephemeral.
+    ///
+    /// If it fails, the author gets told in an ephemeral reply and the details
+    /// are logged. Only failing to send that is returned.
+    pub async fn run_command<E: Error>(
         &self,
-        channel_id: &str,
-    ) -> dashmap::mapref::one::RefMut<SmolStr, InsultData> {
-        if !self.data.insult_data.contains_key(channel_id) {
-            self.data
-                .insult_data
-                .insert(channel_id.into(), Default::default());
+        handler: &dyn Handler<Error = E>,
+        name: &str,
+        args: &[&str],
+    ) -> Result<(), BotError<E>> {
+        let err = match self.try_run_command(handler, name, args).await {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        if err.is_internal() {
+            tracing::error!("couldnt run `{}`: {}", name, err);
+        } else {
+            tracing::debug!("couldnt run `{}`: {}", name, err);
         }
-        self.data.insult_data.get_mut(channel_id).unwrap()
+        handler.send_ephemeral(&err.reply(), None).await?;
+        Ok(())
+    }
+
+    async fn try_run_command<E: Error>(
+        &self,
+        handler: &dyn Handler<Error = E>,
+        name: &str,
+        args: &[&str],
+    ) -> Result<(), BotError<E>> {
+        let channel_id = namespaced(handler.platform(), handler.channel_id());
+        let guild_id = handler
+            .guild_id()
+            .map(|id| namespaced(handler.platform(), id));
+        let context_id = guild_id.clone().unwrap_or_else(|| channel_id.clone());
+        let (channel_id, context_id) = (channel_id.as_str(), context_id.as_str());
+        let command = match self.commands.get(name) {
+            Some(command) => command,
+            None => {
+                if self.limit_message(channel_id, guild_id.as_deref()) {
+                    let id = handler
+                        .send_message(&self.unrecognised_command(name), None, true)
+                        .await?;
+                    self.insult(channel_id, id);
+                }
+                return Ok(());
+            }
+        };
+        let (permission, has_args) = self.permission_for(context_id, command, args);
+        let level = permission.level(has_args);
+        let admin = level > Level::User;
+        let allowed = match permission {
+            Permission::BotOwner => self.is_owner(handler.platform(), handler.author()),
+            _ => !admin || self.author_level(context_id, handler).await? >= level,
+        };
+        if !allowed {
+            return Err(BotError::Permission(level));
+        }
+        let limited = self.limit_command(
+            handler.platform(),
+            handler.author(),
+            channel_id,
+            guild_id.as_deref(),
+            command,
+        );
+        match limited {
+            Verdict::Allowed => {}
+            Verdict::Limited(wait) => return Err(BotError::RateLimited(wait)),
+            Verdict::Ignored => return Ok(()),
+        }
+        if command.is_slow(args) {
+            handler.defer(admin).await?;
+        }
+        let ctx = Context {
+            bot: self,
+            platform: handler.platform(),
+            author: handler.author(),
+            channel_id,
+            guild_id: guild_id.as_deref(),
+            context_id,
+            args,
+        };
+        match command.run(&ctx).await.map_err(BotError::cast)? {
+            Reply::Text(text) if admin => {
+                handler.send_ephemeral(&text, None).await?;
+            }
+            Reply::Text(text) => {
+                handler.send_message(&text, None, true).await?;
+            }
+            Reply::Attachment { name, data } => {
+                handler
+                    .send_message("", Some((name, data.to_vec())), true)
+                    .await?;
+            }
+            Reply::Unrecognised(cmd) => {
+                let id = handler
+                    .send_message(&self.unrecognised_command(&cmd), None, true)
+                    .await?;
+                self.insult(channel_id, id);
+            }
+            Reply::LeaveGuild(guild_id) => {
+                let text = if handler.leave_guild(&guild_id).await? {
+                    format!("left `{}`", guild_id)
+                } else {
+                    format!("couldnt leave `{}`", guild_id)
+                };
+                handler.send_ephemeral(&text, None).await?;
+            }
+            Reply::Broadcast(text) => {
+                let channels = self.speaking_channels(handler.platform());
+                let mut sent = 0;
+                for channel_id in &channels {
+                    match handler.send_to(channel_id, &text).await {
+                        Ok(true) => sent += 1,
+                        Ok(false) => break,
+                        Err(err) => {
+                            tracing::error!("couldnt broadcast to `{}`: {}", channel_id, err)
+                        }
+                    }
+                }
+                let text = format!("sent to {} of {} channels", sent, channels.len());
+                handler.send_ephemeral(&text, None).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn insult_entry(&self, channel_id: &str) -> RefMut<'_, SmolStr, InsultData> {
+        self.data.insult_entry(channel_id)
     }
 
     pub fn insult(&self, channel_id: &str, message_id: SmolStr) {
-        let mut insult_data = self.insult_entry(channel_id);
+        let mut insult_data = self.data.insult_state(channel_id);
         insult_data.count_passed = 1;
         insult_data.message_id = Some(message_id);
     }
@@ -471,31 +1178,29 @@ impl Bot {
                 .data
                 .insult_data
                 .get(channel_id)
-                .map_or(false, |d| d.message_id.as_deref() == Some(message_id))
+                .is_some_and(|d| d.message_id.as_deref() == Some(message_id))
     }
 
     pub fn try_insult(&self, channel_id: &str) -> Option<SmolStr> {
-        let mut insult_data = self.insult_entry(channel_id);
-        if insult_data.enabled
-            && get_rng().gen_bool(0.05 * (insult_data.count_passed as f64) / 100.0)
-        {
-            Some(choose_random_insult().into())
+        let mut insult_data = self.data.insult_state(channel_id);
+        let mut rng = self.rng.lock();
+        if insult_data.enabled && rng.gen_bool(0.05 * (insult_data.count_passed as f64) / 100.0) {
+            Some(self.resources().random_insult(&mut *rng).into())
         } else {
             insult_data.count_passed = insult_data.count_passed.saturating_add(1);
             None
         }
     }
 
-    pub fn gen_user_message(&self, channel_id: &str, message_author: &str) -> SmolStr {
+    pub fn gen_user_message(
+        &self,
+        channel_id: &str,
+        message_author: &str,
+        rng: &mut impl Rng,
+    ) -> SmolStr {
         if let Some(mlisten) = self.data.mchain.get(channel_id) {
             if let Some(chain) = mlisten.per_user.get(message_author) {
-                let tokens = chain.generate();
-                let mut result = String::with_capacity(tokens.iter().map(SmolStr::len).sum());
-                for token in tokens {
-                    result.push_str(&token);
-                    result.push(' ');
-                }
-                result.into()
+                tokenizer::detokenize(chain.generate(rng)).into()
             } else {
                 "User has no messages recorded".into()
             }
@@ -504,55 +1209,354 @@ impl Bot {
         }
     }
 
-    pub fn gen_message(&self, channel_id: &str, token: Option<SmolStr>) -> SmolStr {
-        if let Some(mlisten) = self.data.mchain.get(channel_id) {
-            let tokens = if let Some(token) = token {
-                mlisten.chain.generate_from_token(token)
+    /// Generates from the chain of `channel_id`, falling back to the chain of
+    /// `guild_id` if the channel has nothing to generate from.
+    pub fn gen_message(
+        &self,
+        channel_id: &str,
+        guild_id: Option<&str>,
+        token: Option<SmolStr>,
+        rng: &mut impl Rng,
+    ) -> SmolStr {
+        let mut generate = |chain: &MChain| {
+            let tokens = if let Some(token) = token.clone() {
+                chain.generate_from_token(token, rng)
             } else {
-                mlisten.chain.generate()
+                chain.generate(rng)
             };
-            let mut result = String::with_capacity(tokens.iter().map(SmolStr::len).sum());
-            for token in tokens {
-                result.push_str(&token);
-                result.push(' ');
+            tokenizer::detokenize(tokens).into()
+        };
+        match self.data.mchain.get(channel_id) {
+            Some(mlisten) if !mlisten.chain.is_empty() => generate(&mlisten.chain),
+            mlisten => {
+                let guild = guild_id.and_then(|id| self.data.guilds.get(id));
+                match (guild, mlisten) {
+                    (Some(guild), _) if guild.enabled && !guild.chain.is_empty() => {
+                        generate(&guild.chain)
+                    }
+                    (_, Some(mlisten)) => generate(&mlisten.chain),
+                    (_, None) => CHANNEL_MARK_MSG.into(),
+                }
+            }
+        }
+    }
+
+    pub fn gen_guild_message(&self, guild_id: Option<&str>, rng: &mut impl Rng) -> SmolStr {
+        match guild_id.and_then(|id| self.data.guilds.get(id)) {
+            Some(guild) if guild.enabled => tokenizer::detokenize(guild.chain.generate(rng)).into(),
+            _ => "this server has no chain, turn it on with `listen server`".into(),
+        }
+    }
+
+    /// Generates from the chain of another channel, as long as it's in the
+    /// same guild.
+    pub fn gen_channel_message(
+        &self,
+        platform: &str,
+        guild_id: Option<&str>,
+        channel: &str,
+        rng: &mut impl Rng,
+    ) -> SmolStr {
+        let channel_id = namespaced(platform, parse_channel_id(channel));
+        match self.data.mchain.get(&channel_id) {
+            Some(mlisten) if guild_id.is_some() && mlisten.guild_id.as_deref() == guild_id => {
+                tokenizer::detokenize(mlisten.chain.generate(rng)).into()
             }
-            result.into()
+            _ => "that channel isn't listened to in this server".into(),
+        }
+    }
+
+    /// Logs the message to the corpus and learns it, if the channel learns
+    /// and the author didn't opt out.
+    pub async fn markov_learn<E>(&self, channel_id: &str, handler: &dyn Handler<Error = E>) {
+        let (author, content) = (handler.author(), handler.content());
+        if self.is_opted_out(&namespaced(handler.platform(), author)) {
+            return;
+        }
+        if !self.data.mchain.get(channel_id).is_some_and(|m| m.learn) {
+            return;
+        }
+        // logged even if it's filtered, so changing the filter and rebuilding
+        // can bring it back
+        let logged = match self.corpus.clone() {
+            Some(corpus) => {
+                let entry = corpus::Entry::new(author, handler.author_is_bot(), content);
+                let channel_id = SmolStr::new(channel_id);
+                tokio::task::spawn_blocking(move || corpus.append(&channel_id, &entry))
+                    .await
+                    .map_err(io::Error::other)
+                    .and_then(|appended| appended)
+                    .map_err(|err| tracing::error!("couldnt log message: {}", err))
+                    .is_ok()
+            }
+            None => false,
+        };
+        if let Some(mut mlisten) = self.data.markov_mut(channel_id) {
+            if mlisten.guild_id.is_none() {
+                mlisten.guild_id = handler
+                    .guild_id()
+                    .map(|id| namespaced(handler.platform(), id));
+            }
+            if !logged {
+                mlisten.fully_logged = false;
+            } else if mlisten.is_empty() {
+                mlisten.fully_logged = true;
+            }
+            let tokens = mlisten.learn(author, handler.author_is_bot(), content);
+            let guild_id = mlisten.guild_id.clone();
+            drop(mlisten);
+            if let Some((tokens, guild_id)) = tokens.zip(guild_id) {
+                if let Some(mut guild) = self.data.guild_mut(&guild_id) {
+                    if guild.enabled {
+                        guild.chain.feed(tokens);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn is_opted_out(&self, user_id: &str) -> bool {
+        self.data
+            .users
+            .get(user_id)
+            .is_some_and(|user| user.opted_out)
+    }
+
+    pub fn privacy_set_opt_out(&self, user_id: &str, opted_out: bool) -> SmolStr {
+        self.data.user_entry(user_id).opted_out = opted_out;
+        if opted_out {
+            "Fine, I won't listen to you anymore. Use `privacy forget` to make me forget what you already said."
+                .into()
         } else {
-            CHANNEL_MARK_MSG.into()
+            "I'll listen to you again.".into()
         }
     }
 
+    /// Forgets everything `author` said in the channels of `platform`.
+    pub async fn privacy_forget(&self, platform: &str, author: &str) -> SmolStr {
+        let prefix = format!("{}:", platform);
+        match self
+            .forget_author(author, move |channel_id| channel_id.starts_with(&prefix))
+            .await
+        {
+            Ok(forgotten) => format!(
+                "forgot your messages in {} channels{}",
+                forgotten.channels,
+                forgotten.tangled_note()
+            )
+            .into(),
+            Err(err) => {
+                tracing::error!("couldnt forget `{}`: {}", author, err);
+                "couldnt forget you, try again later".into()
+            }
+        }
+    }
+
+    /// Removes everything `author` said in the channels `in_channel` accepts,
+    /// from their per user chains, the corpus and, if it has every message
+    /// they learned, the channel chains.
+    async fn forget_author<F>(&self, author: &str, in_channel: F) -> io::Result<Forgotten>
+    where
+        F: Fn(&str) -> bool + Clone + Send + 'static,
+    {
+        let with_user_chain = self
+            .data
+            .mchain
+            .iter()
+            .filter(|e| in_channel(e.key()) && e.per_user.contains_key(author))
+            .map(|e| e.key().clone())
+            .collect::<Vec<_>>();
+        for channel_id in &with_user_chain {
+            if let Some(data) = self.data.markov_mut(channel_id) {
+                data.per_user.remove(author);
+            }
+        }
+
+        let corpus = match self.corpus.clone() {
+            Some(corpus) => corpus,
+            None => {
+                return Ok(Forgotten {
+                    channels: with_user_chain.len(),
+                    tangled: with_user_chain.len(),
+                })
+            }
+        };
+        let author_owned = author.to_owned();
+        let changed = tokio::task::spawn_blocking(move || -> io::Result<Vec<SmolStr>> {
+            let mut changed = Vec::new();
+            for channel_id in corpus.channels()? {
+                if in_channel(&channel_id) && corpus.remove_author(&channel_id, &author_owned)? > 0
+                {
+                    changed.push(channel_id);
+                }
+            }
+            Ok(changed)
+        })
+        .await
+        .map_err(io::Error::other)??;
+
+        let mut tangled = 0;
+        for channel_id in &changed {
+            let fully_logged = self
+                .data
+                .mchain
+                .get(channel_id)
+                .is_some_and(|data| data.fully_logged);
+            if !fully_logged {
+                tangled += 1;
+                continue;
+            }
+            if let Err(err) = self.markov_rebuild(channel_id).await {
+                tracing::error!("couldnt rebuild `{}`: {}", channel_id, err);
+                tangled += 1;
+            }
+        }
+        let channels = with_user_chain
+            .iter()
+            .chain(&changed)
+            .collect::<HashSet<_>>();
+        self.guilds_rebuild(channels.iter().copied()).await;
+        Ok(Forgotten {
+            channels: channels.len(),
+            tangled,
+        })
+    }
+
+    /// Handles `listen clear`, which only does anything once `confirm` is
+    /// added to the end.
+    pub async fn markov_clear<'a>(
+        &self,
+        channel_id: &str,
+        guild_id: Option<&str>,
+        args: impl Iterator<Item = &'a str>,
+    ) -> SmolStr {
+        let mut args = args.collect::<Vec<_>>();
+        let confirmed = args.last() == Some(&"confirm");
+        if confirmed {
+            args.pop();
+        }
+        let scope = match (args.as_slice(), guild_id) {
+            ([], _) | (["all"], None) => ClearScope::Channel,
+            (["all"], Some(guild_id)) => ClearScope::Guild(guild_id),
+            (["user", user_id], _) => ClearScope::User(parse_user_id(user_id)),
+            _ => return "expected `clear`, `clear user <id>` or `clear all`".into(),
+        };
+
+        if !confirmed {
+            let what = match &scope {
+                ClearScope::Channel => "everything learned in this channel".to_owned(),
+                ClearScope::Guild(_) => {
+                    "everything learned in every channel of this server".to_owned()
+                }
+                ClearScope::User(user_id) => {
+                    format!("everything learned from `{}` in this channel", user_id)
+                }
+            };
+            return format!(
+                "this forgets {} and can't be undone. add `confirm` to the end of the command if you're sure.",
+                what
+            )
+            .into();
+        }
+
+        match scope {
+            ClearScope::Channel => {
+                let res = self.markov_clear_channel(channel_id).await;
+                self.guilds_rebuild([&channel_id.into()]).await;
+                match res {
+                    Ok(()) => SmolStr::new_inline("cleared data"),
+                    Err(err) => {
+                        tracing::error!("couldnt clear `{}`: {}", channel_id, err);
+                        "cleared the chains, but couldnt delete the corpus".into()
+                    }
+                }
+            }
+            ClearScope::Guild(guild_id) => {
+                let channels = self
+                    .data
+                    .mchain
+                    .iter()
+                    .filter(|e| e.key() == channel_id || e.guild_id.as_deref() == Some(guild_id))
+                    .map(|e| e.key().clone())
+                    .collect::<Vec<_>>();
+                for channel_id in &channels {
+                    if let Err(err) = self.markov_clear_channel(channel_id).await {
+                        tracing::error!("couldnt clear `{}`: {}", channel_id, err);
+                    }
+                }
+                if let Some(mut guild) = self.data.guild_mut(guild_id) {
+                    guild.chain = MChain::new();
+                }
+                format!("cleared data of {} channels", channels.len()).into()
+            }
+            ClearScope::User(user_id) => {
+                let channel_owned = SmolStr::from(channel_id);
+                match self
+                    .forget_author(&user_id, move |id| id == channel_owned)
+                    .await
+                {
+                    Ok(forgotten) if forgotten.channels == 0 => {
+                        "nothing was learned from them here".into()
+                    }
+                    Ok(forgotten) => format!(
+                        "forgot `{}` in this channel{}",
+                        user_id,
+                        forgotten.tangled_note()
+                    )
+                    .into(),
+                    Err(err) => {
+                        tracing::error!("couldnt forget `{}`: {}", user_id, err);
+                        "couldnt forget them, try again later".into()
+                    }
+                }
+            }
+        }
+    }
+
+    /// Empties the chains and corpus of `channel_id`, keeping its settings.
+    async fn markov_clear_channel(&self, channel_id: &str) -> io::Result<()> {
+        let res = match self.corpus.clone() {
+            Some(corpus) => {
+                let channel_id = SmolStr::new(channel_id);
+                tokio::task::spawn_blocking(move || corpus.remove(&channel_id))
+                    .await
+                    .map_err(io::Error::other)
+                    .and_then(|removed| removed)
+            }
+            None => Ok(()),
+        };
+        if let Some(mut data) = self.data.markov_mut(channel_id) {
+            data.clear_chains();
+            data.fully_logged = self.corpus.is_some() && res.is_ok();
+        }
+        res
+    }
+
     pub fn markov_try_gen_message(
         &self,
         channel_id: &str,
         message_content: &str,
-        message_author: &str,
     ) -> Option<(SmolStr, bool)> {
-        if let Some(mut mlisten) = self.data.mchain.get_mut(channel_id) {
-            let mut tokens = message_content
-                .split_whitespace()
-                .map(SmolStr::new)
-                .collect::<Vec<_>>();
-            mlisten.chain.feed(&tokens);
-            mlisten
-                .per_user
-                .entry(message_author.into())
-                .or_default()
-                .feed(&tokens);
-            let mut rng = get_rng();
-            if mlisten.enabled && rng.gen_bool(mlisten.probability / 100.0) {
+        if let Some(mlisten) = self.data.mchain.get(channel_id) {
+            let mut tokens = tokenizer::tokenize(message_content);
+            let mut rng = self.rng.lock();
+            let rng = &mut *rng;
+            if mlisten.speak
+                && !mlisten.chain.is_empty()
+                && rng.gen_bool(mlisten.probability / 100.0)
+            {
                 let is_reply = rng.gen_bool(1.0 / 5.0);
                 let start_token = if tokens.is_empty().not() && is_reply {
                     tokens.remove(rng.gen_range(0..tokens.len()))
                 } else {
-                    mlisten.chain.iter_for(1).next().unwrap().pop().unwrap()
+                    mlisten.chain.generate(rng).pop()?
                 };
 
                 let mut tokens = mlisten
                     .chain
-                    .generate_from_token(start_token.clone())
+                    .generate_from_token(start_token.clone(), rng)
                     .into_iter()
-                    .map(|s| typo(s, &mut rng))
+                    .map(|s| typo(s, rng))
                     .collect::<Vec<_>>();
 
                 if tokens.is_empty() || (tokens.len() == 1 && tokens[0] == start_token) {
@@ -560,50 +1564,50 @@ impl Bot {
                     tokens.append(
                         &mut mlisten
                             .chain
-                            .generate()
+                            .generate(rng)
                             .into_iter()
-                            .map(|s| typo(s, &mut rng))
+                            .map(|s| typo(s, rng))
                             .collect::<Vec<_>>(),
                     );
                 }
 
                 tokens.truncate(rng.gen_range(16..32));
 
-                let mut result = String::with_capacity(tokens.iter().map(SmolStr::len).sum());
-                for token in tokens {
-                    result.push_str(&token);
-                    result.push(' ');
-                }
-                return Some((result.into(), is_reply));
+                return Some((tokenizer::detokenize(tokens).into(), is_reply));
             }
         }
         None
     }
 
     pub fn unrecognised_command(&self, cmd: &str) -> SmolStr {
-        format!("{}`{}` isn't a command.", choose_random_insult(), cmd).into()
+        let resources = self.resources();
+        let insult = resources.random_insult(&mut *self.rng.lock());
+        format!("{}`{}` isn't a command.", insult, cmd).into()
     }
 
-    pub fn generate_poem(&self) -> SmolStr {
-        let poem_chain = &self.poem_chain;
+    pub fn generate_poem(&self, rng: &mut impl Rng) -> SmolStr {
+        let resources = self.resources();
+        let poem_chain = resources.poem_chain();
 
         let mut output = String::new();
-        let some_tokens = poem_chain.generate();
+        let some_tokens = poem_chain.generate(rng);
 
-        let mut rng = get_rng();
-        let start_token = some_tokens
+        let capitalised = some_tokens
             .iter()
-            .filter(|c| c.chars().next().unwrap().is_uppercase())
-            .choose(&mut rng)
-            .unwrap()
-            .clone();
+            .filter(|c| c.starts_with(char::is_uppercase))
+            .choose(rng);
+        // custom poems don't have to have any capitalised words, or any at all
+        let start_token = match capitalised.or_else(|| some_tokens.iter().choose(rng)) {
+            Some(token) => token.clone(),
+            None => return SmolStr::default(),
+        };
         let seperate_by = rng.gen_range(2..=3);
         let poem_lines = rng.gen_range(6..=8);
         let is_sentence_end = |c| matches!(c, '.' | '!' | '?');
         let mut sentences = Vec::with_capacity(poem_lines);
         let mut sentence = Vec::new();
         let mut sentence_count = 0;
-        for token in poem_chain.generate_from_token(start_token) {
+        for token in poem_chain.generate_from_token(start_token, rng) {
             if sentence_count > 7 {
                 break;
             }
@@ -616,10 +1620,7 @@ impl Bot {
             }
         }
         for (index, sentence) in sentences.into_iter().enumerate() {
-            for word in sentence {
-                output.push_str(&word);
-                output.push(' ');
-            }
+            output.push_str(&tokenizer::detokenize(sentence));
             output.push('\n');
             if index % seperate_by == 0 {
                 output.push('\n');
@@ -628,55 +1629,107 @@ impl Bot {
         output.into()
     }
 
+    /// A random poem without `keywords`, otherwise the one matching them best.
     pub fn process_poem_command(&self, keywords: &str) -> SmolStr {
+        let resources = self.resources();
         if keywords.is_empty() {
-            choose_random_poem().into()
-        } else {
-            let ranker = fuzzy_matcher::skim::SkimMatcherV2::default();
-            let mut ranked = POEMS
-                .split('-')
-                .filter_map(|choice| {
-                    let score = ranker.fuzzy(choice, keywords, false)?.0;
-                    (score > 10).then(|| (choice, score))
-                })
-                .collect::<Vec<_>>();
-            ranked.sort_unstable_by_key(|(_, k)| *k);
-            if let Some((result, _)) = ranked.last() {
-                (*result).into()
-            } else {
-                "No poem with those words. Try again, maybe a miracle will occur.".into()
-            }
+            return resources.random_poem(&mut *self.rng.lock()).into();
+        }
+        match resources.poem_index().search(keywords).first() {
+            Some(hit) => resources.poems().nth(hit.index).unwrap_or_default().into(),
+            None => NO_POEM_MSG.into(),
+        }
+    }
+
+    /// The poem numbered `number` (`#3` or `3`), counting from 1.
+    pub fn poem_by_number(&self, number: &str) -> Result<SmolStr, BotError> {
+        let number = number
+            .trim_start_matches('#')
+            .parse::<usize>()
+            .map_err(|_| BotError::InvalidArgument("poem numbers look like `#3`".into()))?;
+        let resources = self.resources();
+        match resources.poem(number) {
+            Some(poem) => Ok(poem.into()),
+            None => Err(BotError::NotFound(
+                format!(
+                    "poem #{}, there are only {} of them",
+                    number,
+                    resources.poems().count()
+                )
+                .into(),
+            )),
         }
     }
-}
 
-pub fn default_poem_chain() -> MChain {
-    let mut chain = Chain::new();
-    chain.feed(
-        &POEMS
-            .replace('-', "")
-            .split_whitespace()
-            .map(SmolStr::new)
-            .collect::<Vec<_>>(),
-    );
-    chain
+    /// Page `page` (counting from 1) of the poems matching `query`, best
+    /// first, with their number, first line and a line matching the query.
+    pub fn poem_search(&self, query: &str, page: usize) -> Result<SmolStr, BotError> {
+        let resources = self.resources();
+        let hits = resources.poem_index().search(query);
+        if hits.is_empty() {
+            return Ok(NO_POEM_MSG.into());
+        }
+        let pages = hits.len().div_ceil(POEMS_PER_PAGE);
+        if page == 0 || page > pages {
+            return Err(BotError::NotFound(
+                format!("page {} of the results, they only have {}", page, pages).into(),
+            ));
+        }
+        let poems = resources.poems().collect::<Vec<_>>();
+        let mut text = format!("poems matching `{}`, page {} of {}:", query, page, pages);
+        for hit in hits
+            .iter()
+            .skip((page - 1) * POEMS_PER_PAGE)
+            .take(POEMS_PER_PAGE)
+        {
+            let mut lines = poems[hit.index]
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty());
+            let title = lines.next().unwrap_or_default();
+            text.push_str(&format!("\n- `#{}` {}", hit.index + 1, title));
+            if !search::has_match(title, query) {
+                if let Some(line) = lines.find(|line| search::has_match(line, query)) {
+                    text.push_str(&format!("\n  > {}", line));
+                }
+            }
+        }
+        text.push_str("\n\nuse `poem #<n>` to read one");
+        if page < pages {
+            text.push_str(&format!(
+                ", and `poem search {} {}` for more",
+                query,
+                page + 1
+            ));
+        }
+        Ok(text.into())
+    }
 }
 
-pub fn choose_random_poem() -> &'static str {
-    POEMS
-        .split('-')
-        .choose(&mut rand::thread_rng())
-        .expect("always something in poems")
+/// Prefixes a platform specific id with the platform name, so ids from
+/// different platforms can't collide.
+pub fn namespaced(platform: &str, id: &str) -> SmolStr {
+    format!("{}:{}", platform, id).into()
 }
 
-pub fn choose_random_insult() -> &'static str {
-    INSULTS
-        .split('-')
-        .choose(&mut rand::thread_rng())
-        .expect("always something in insults")
+/// The resources in the configured directory, or the built in ones if there
+/// is none or they couldn't be read.
+fn load_resources(config: &Config) -> Resources {
+    let dir = match &config.resources_dir {
+        Some(dir) => dir,
+        None => return Resources::builtin(),
+    };
+    Resources::load(dir).unwrap_or_else(|err| {
+        tracing::error!(
+            "couldnt load resources from `{}`, using the built in ones: {}",
+            dir.display(),
+            err
+        );
+        Resources::builtin()
+    })
 }
 
-fn typo(s: SmolStr, rng: &mut impl rand::Rng) -> SmolStr {
+fn typo(s: SmolStr, rng: &mut impl Rng) -> SmolStr {
     let mut chars = Vec::with_capacity(s.len());
     for ch in s.chars() {
         let ch = if rng.gen_bool(0.5 / 100.0) {
@@ -689,10 +1742,6 @@ fn typo(s: SmolStr, rng: &mut impl rand::Rng) -> SmolStr {
     chars.into_iter().collect()
 }
 
-fn get_rng() -> SmallRng {
-    SmallRng::from_entropy()
-}
-
 #[macro_export]
 macro_rules! perr {
     ($res:expr) => {
@@ -1,10 +1,22 @@
+use bernbot::config::Config;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
-const LOG_FILENAME: &str = "log";
-
 fn main() {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
     let term_logger = fmt::layer();
-    let file_appender = tracing_appender::rolling::never(".", LOG_FILENAME);
+    let log_dir = match config.log_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => ".".as_ref(),
+    };
+    let log_name = config.log_file.file_name().expect("validated by config");
+    let file_appender = tracing_appender::rolling::never(log_dir, log_name);
     let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
     let file_logger = fmt::layer().with_ansi(false).with_writer(non_blocking);
 
@@ -19,8 +31,15 @@ fn main() {
         .build()
         .unwrap();
 
-    #[cfg(feature = "discord")]
-    runtime.block_on(bernbot::discord::main());
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        None => runtime.block_on(bernbot::runtime::run(config)),
+        Some("rebuild") => runtime.block_on(bernbot::runtime::rebuild(config, args.collect())),
+        Some(cmd) => {
+            eprintln!("unknown command `{}`, available commands are: rebuild", cmd);
+            std::process::exit(1);
+        }
+    }
 
     runtime.shutdown_background();
 }
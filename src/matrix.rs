@@ -0,0 +1,693 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{perms::Level, runtime::Shutdown, BotError, Handler};
+
+use super::{perr, Bot};
+use reqwest::{Method, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use smol_str::SmolStr;
+use tokio::sync::mpsc;
+
+const SYNC_TIMEOUT_MS: u64 = 30 * 1000;
+const SYNC_RETRY_SECS: u64 = 10;
+/// Power level clients call admin. Rooms have no owner, so that's the most a
+/// user can be.
+const ADMIN_POWER_LEVEL: i64 = 100;
+
+#[derive(Debug, Default, Deserialize)]
+struct SyncResponse {
+    next_batch: String,
+    #[serde(default)]
+    rooms: Rooms,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Rooms {
+    #[serde(default)]
+    join: HashMap<SmolStr, JoinedRoom>,
+    #[serde(default)]
+    invite: HashMap<SmolStr, Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct JoinedRoom {
+    #[serde(default)]
+    timeline: Timeline,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Timeline {
+    #[serde(default)]
+    events: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    event_id: SmolStr,
+    sender: SmolStr,
+    #[serde(default)]
+    content: Value,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PowerLevels {
+    #[serde(default)]
+    users: HashMap<SmolStr, i64>,
+    #[serde(default)]
+    users_default: i64,
+    #[serde(default = "default_state_level")]
+    state_default: i64,
+}
+
+fn default_state_level() -> i64 {
+    50
+}
+
+/// A minimal client for the parts of the Matrix client-server API the bot uses.
+struct Client {
+    http: reqwest::Client,
+    homeserver: Url,
+    token: String,
+    txn_prefix: u128,
+    txn_count: AtomicU64,
+}
+
+impl Client {
+    fn new(homeserver: Url, token: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            homeserver,
+            token,
+            txn_prefix: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis()),
+            txn_count: AtomicU64::new(0),
+        }
+    }
+
+    fn endpoint(&self, segments: &[&str]) -> Url {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .expect("homeserver url can't be a base")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    fn client_endpoint(&self, segments: &[&str]) -> Url {
+        let mut url = self.endpoint(&["_matrix", "client", "v3"]);
+        url.path_segments_mut()
+            .expect("homeserver url can't be a base")
+            .extend(segments);
+        url
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        url: Url,
+        body: Option<&Value>,
+    ) -> Result<T, reqwest::Error> {
+        let mut req = self.http.request(method, url).bearer_auth(&self.token);
+        if let Some(body) = body {
+            req = req.json(body);
+        }
+        req.send().await?.error_for_status()?.json().await
+    }
+
+    fn next_txn_id(&self) -> String {
+        let count = self.txn_count.fetch_add(1, Ordering::Relaxed);
+        format!("{}-{}", self.txn_prefix, count)
+    }
+
+    async fn whoami(&self) -> Result<SmolStr, reqwest::Error> {
+        let resp: Value = self
+            .request(
+                Method::GET,
+                self.client_endpoint(&["account", "whoami"]),
+                None,
+            )
+            .await?;
+        Ok(resp["user_id"].as_str().unwrap_or_default().into())
+    }
+
+    async fn sync(&self, since: Option<&str>) -> Result<SyncResponse, reqwest::Error> {
+        let mut url = self.client_endpoint(&["sync"]);
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("timeout", &SYNC_TIMEOUT_MS.to_string());
+            if let Some(since) = since {
+                query.append_pair("since", since);
+            }
+        }
+        self.request(Method::GET, url, None).await
+    }
+
+    async fn join(&self, room_id: &str) -> Result<(), reqwest::Error> {
+        self.request(
+            Method::POST,
+            self.client_endpoint(&["rooms", room_id, "join"]),
+            Some(&json!({})),
+        )
+        .await
+        .map(|_: Value| ())
+    }
+
+    async fn leave(&self, room_id: &str) -> Result<(), reqwest::Error> {
+        self.request(
+            Method::POST,
+            self.client_endpoint(&["rooms", room_id, "leave"]),
+            Some(&json!({})),
+        )
+        .await
+        .map(|_: Value| ())
+    }
+
+    async fn set_typing(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        typing: bool,
+    ) -> Result<(), reqwest::Error> {
+        self.request(
+            Method::PUT,
+            self.client_endpoint(&["rooms", room_id, "typing", user_id]),
+            Some(&json!({ "typing": typing, "timeout": 5000 })),
+        )
+        .await
+        .map(|_: Value| ())
+    }
+
+    async fn send(&self, room_id: &str, content: &Value) -> Result<SmolStr, reqwest::Error> {
+        let txn_id = self.next_txn_id();
+        let resp: Value = self
+            .request(
+                Method::PUT,
+                self.client_endpoint(&["rooms", room_id, "send", "m.room.message", &txn_id]),
+                Some(content),
+            )
+            .await?;
+        Ok(resp["event_id"].as_str().unwrap_or_default().into())
+    }
+
+    async fn upload(&self, name: &str, data: Vec<u8>) -> Result<SmolStr, reqwest::Error> {
+        let mut url = self.endpoint(&["_matrix", "media", "v3", "upload"]);
+        url.query_pairs_mut().append_pair("filename", name);
+        let resp: Value = self
+            .http
+            .post(url)
+            .bearer_auth(&self.token)
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .body(data)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(resp["content_uri"].as_str().unwrap_or_default().into())
+    }
+
+    async fn power_levels(&self, room_id: &str) -> Result<PowerLevels, reqwest::Error> {
+        self.request(
+            Method::GET,
+            self.client_endpoint(&["rooms", room_id, "state", "m.room.power_levels", ""]),
+            None,
+        )
+        .await
+    }
+}
+
+struct MatrixHandler {
+    client: Arc<Client>,
+    bot: Bot,
+    user_id: SmolStr,
+    id: SmolStr,
+    author: SmolStr,
+    content: String,
+    channel_id: SmolStr,
+    referenced_id: Option<SmolStr>,
+}
+
+#[async_trait::async_trait]
+impl Handler for MatrixHandler {
+    type Error = reqwest::Error;
+
+    async fn author_level(&self) -> Result<Level, BotError<Self::Error>> {
+        let levels = self.client.power_levels(&self.channel_id).await?;
+        let level = levels
+            .users
+            .get(&self.author)
+            .copied()
+            .unwrap_or(levels.users_default);
+        Ok(if level >= ADMIN_POWER_LEVEL {
+            Level::Admin
+        } else if level >= levels.state_default {
+            Level::Moderator
+        } else {
+            Level::User
+        })
+    }
+
+    async fn send_message(
+        &self,
+        text: &str,
+        attach: Option<(&str, Vec<u8>)>,
+        reply: bool,
+    ) -> Result<SmolStr, BotError<Self::Error>> {
+        perr!(
+            self.client
+                .set_typing(&self.channel_id, &self.user_id, true)
+                .await
+        );
+        tokio::time::sleep(self.bot.typing_delay()).await;
+
+        let mut content = if let Some((name, data)) = attach {
+            let url = self.client.upload(name, data).await?;
+            json!({ "msgtype": "m.image", "body": name, "url": url })
+        } else {
+            json!({ "msgtype": "m.text", "body": text })
+        };
+        if reply {
+            content["m.relates_to"] = json!({ "m.in_reply_to": { "event_id": self.id } });
+        }
+        let id = self.client.send(&self.channel_id, &content).await?;
+
+        perr!(
+            self.client
+                .set_typing(&self.channel_id, &self.user_id, false)
+                .await
+        );
+        Ok(id)
+    }
+
+    /// Rooms are what servers are elsewhere, so this leaves the room.
+    async fn leave_guild(&self, guild_id: &str) -> Result<bool, BotError<Self::Error>> {
+        self.client.leave(guild_id).await?;
+        Ok(true)
+    }
+
+    async fn send_to(&self, channel_id: &str, text: &str) -> Result<bool, BotError<Self::Error>> {
+        let content = json!({ "msgtype": "m.text", "body": text });
+        self.client.send(channel_id, &content).await?;
+        Ok(true)
+    }
+
+    fn platform(&self) -> &str {
+        "matrix"
+    }
+
+    fn bot_user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    fn author_is_bot(&self) -> bool {
+        // matrix doesn't mark bot accounts
+        false
+    }
+
+    fn referenced_id(&self) -> Option<&str> {
+        self.referenced_id.as_deref()
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn author(&self) -> &str {
+        &self.author
+    }
+
+    fn content(&self) -> &str {
+        &self.content
+    }
+
+    fn channel_id(&self) -> &str {
+        &self.channel_id
+    }
+
+    /// Rooms are what servers are elsewhere, so a room is both.
+    fn guild_id(&self) -> Option<&str> {
+        Some(&self.channel_id)
+    }
+}
+
+/// Strips the quoted fallback that clients prepend to the body of replies.
+fn strip_reply_fallback(body: &str) -> &str {
+    if !body.starts_with("> ") {
+        return body;
+    }
+    body.split_once("\n\n").map_or(body, |(_, rest)| rest)
+}
+
+fn handler_for_event(
+    client: &Arc<Client>,
+    bot: &Bot,
+    user_id: &SmolStr,
+    room_id: &SmolStr,
+    event: Value,
+) -> Option<MatrixHandler> {
+    let event: Event = serde_json::from_value(event).ok()?;
+    if event.kind != "m.room.message" || event.content["msgtype"] != "m.text" {
+        return None;
+    }
+    let body = event.content["body"].as_str()?;
+    let referenced_id = event.content["m.relates_to"]["m.in_reply_to"]["event_id"]
+        .as_str()
+        .map(SmolStr::new);
+    let content = if referenced_id.is_some() {
+        strip_reply_fallback(body)
+    } else {
+        body
+    };
+
+    Some(MatrixHandler {
+        client: client.clone(),
+        bot: bot.clone(),
+        user_id: user_id.clone(),
+        id: event.event_id,
+        author: event.sender,
+        content: content.to_owned(),
+        channel_id: room_id.clone(),
+        referenced_id,
+    })
+}
+
+/// Handles the messages of a room one after the other, in the order they were
+/// sent.
+fn spawn_room_worker(bot: &Bot) -> mpsc::UnboundedSender<MatrixHandler> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<MatrixHandler>();
+    let bot = bot.clone();
+    tokio::spawn(async move {
+        while let Some(handler) = rx.recv().await {
+            perr!(bot.process_args(&handler).await);
+        }
+    });
+    tx
+}
+
+pub async fn run(bot: Bot, shutdown: Shutdown) {
+    let (homeserver, token) = match (
+        std::env::var("MATRIX_HOMESERVER"),
+        std::env::var("MATRIX_TOKEN"),
+    ) {
+        (Ok(homeserver), Ok(token)) => (homeserver, token),
+        _ => {
+            tracing::error!("MATRIX_HOMESERVER or MATRIX_TOKEN is not set, not starting matrix");
+            return;
+        }
+    };
+    let homeserver = match Url::parse(&homeserver) {
+        Ok(url) => url,
+        Err(err) => {
+            tracing::error!("invalid homeserver url `{}`: {}", homeserver, err);
+            return;
+        }
+    };
+    run_client(bot, shutdown, Arc::new(Client::new(homeserver, token))).await;
+}
+
+async fn run_client(bot: Bot, mut shutdown: Shutdown, client: Arc<Client>) {
+    let user_id = match client.whoami().await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            tracing::error!("couldnt get matrix user: {}", err);
+            return;
+        }
+    };
+
+    // the first sync only gets us up to date, we don't want to answer old messages
+    let mut since = None;
+    let mut workers = HashMap::new();
+    loop {
+        let resp = tokio::select! {
+            resp = client.sync(since.as_deref()) => resp,
+            _ = shutdown.wait() => break,
+        };
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(err) => {
+                tracing::error!("couldnt sync: {}", err);
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(SYNC_RETRY_SECS)) => continue,
+                    _ = shutdown.wait() => break,
+                }
+            }
+        };
+        let is_initial = since.is_none();
+        since = Some(resp.next_batch);
+
+        for room_id in resp.rooms.invite.keys() {
+            perr!(client.join(room_id).await);
+        }
+        if is_initial {
+            continue;
+        }
+        for (room_id, room) in resp.rooms.join {
+            for event in room.timeline.events {
+                let handler = match handler_for_event(&client, &bot, &user_id, &room_id, event) {
+                    Some(handler) => handler,
+                    None => continue,
+                };
+                let worker = workers
+                    .entry(room_id.clone())
+                    .or_insert_with(|| spawn_room_worker(&bot));
+                // the worker only stops if handling a message panicked
+                if let Err(mpsc::error::SendError(handler)) = worker.send(handler) {
+                    *worker = spawn_room_worker(&bot);
+                    let _ = worker.send(handler);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use parking_lot::Mutex;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::watch,
+    };
+
+    use super::*;
+    use crate::{config::Config, storage::FileStorage};
+
+    const ROOM_ID: &str = "!room:mock";
+    const OWNER_ID: &str = "@owner:mock";
+
+    /// A homeserver that invites the bot to a room, then sends it a few
+    /// messages, and records the requests it gets.
+    #[derive(Default)]
+    struct Homeserver {
+        fail_sync: bool,
+        syncs: AtomicUsize,
+        /// Method, path and body of every request but syncs.
+        requests: Mutex<Vec<(String, String, Value)>>,
+    }
+
+    impl Homeserver {
+        async fn respond(&self, method: &str, path: &str, body: Value) -> (u16, Value) {
+            if path.starts_with("/_matrix/client/v3/sync") {
+                if self.fail_sync {
+                    return (500, json!({}));
+                }
+                let events = ["b/poem #5", "b/poem #6", "b/owner leave !room:mock"]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, body)| {
+                        json!({
+                            "type": "m.room.message",
+                            "event_id": format!("$event{}", i),
+                            "sender": OWNER_ID,
+                            "content": { "msgtype": "m.text", "body": body },
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                return match self.syncs.fetch_add(1, Ordering::SeqCst) {
+                    0 => (
+                        200,
+                        json!({ "next_batch": "1", "rooms": { "invite": { ROOM_ID: {} } } }),
+                    ),
+                    1 => (
+                        200,
+                        json!({
+                            "next_batch": "2",
+                            "rooms": { "join": { ROOM_ID: { "timeline": { "events": events } } } },
+                        }),
+                    ),
+                    _ => std::future::pending().await,
+                };
+            }
+            let mut requests = self.requests.lock();
+            requests.push((method.to_owned(), path.to_owned(), body));
+            if path.starts_with("/_matrix/client/v3/account/whoami") {
+                (200, json!({ "user_id": "@bot:mock" }))
+            } else if path.contains("/send/") {
+                (
+                    200,
+                    json!({ "event_id": format!("$sent{}", requests.len()) }),
+                )
+            } else {
+                (200, json!({}))
+            }
+        }
+
+        async fn serve_connection(&self, mut stream: TcpStream) {
+            let mut buf = Vec::new();
+            let head_len = loop {
+                if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break i + 4;
+                }
+                let mut chunk = [0; 1024];
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                }
+            };
+            let head = String::from_utf8_lossy(&buf[..head_len]).into_owned();
+            let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+            let method = request_line.next().unwrap_or_default();
+            let path = request_line.next().unwrap_or_default();
+            let content_len = head
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                .and_then(|(_, len)| len.trim().parse().ok())
+                .unwrap_or(0);
+            let mut body = buf.split_off(head_len);
+            while body.len() < content_len {
+                let mut chunk = [0; 1024];
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => body.extend_from_slice(&chunk[..n]),
+                }
+            }
+            let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+            let (status, resp) = self.respond(method, path, body).await;
+            let resp = resp.to_string();
+            let resp = format!(
+                "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                resp.len(),
+                resp
+            );
+            let _ = stream.write_all(resp.as_bytes()).await;
+        }
+
+        /// Starts serving on a free port, returning its url.
+        async fn start(self: &Arc<Self>) -> Url {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/", listener.local_addr().unwrap());
+            let homeserver = self.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let homeserver = homeserver.clone();
+                    tokio::spawn(async move { homeserver.serve_connection(stream).await });
+                }
+            });
+            url.parse().unwrap()
+        }
+
+        fn requests(&self) -> Vec<(String, String, Value)> {
+            self.requests.lock().clone()
+        }
+    }
+
+    fn bot() -> Bot {
+        let config = Config {
+            owners: vec![format!("matrix:{}", OWNER_ID).into()],
+            typing_delay_ms: (0, 0),
+            user_rate_limit: None,
+            channel_rate_limit: None,
+            guild_rate_limit: None,
+            cooldowns: Default::default(),
+            rng_seed: Some(0),
+            ..Config::default()
+        };
+        let storage = FileStorage::new(std::env::temp_dir().join("bernbot-matrix-test"));
+        Bot::new(Arc::new(storage), Arc::new(config))
+    }
+
+    async fn start(
+        homeserver: &Arc<Homeserver>,
+    ) -> (watch::Sender<bool>, tokio::task::JoinHandle<()>) {
+        let client = Arc::new(Client::new(homeserver.start().await, "token".into()));
+        let (tx, rx) = watch::channel(false);
+        let task = tokio::spawn(run_client(bot(), Shutdown(rx), client));
+        (tx, task)
+    }
+
+    #[tokio::test]
+    async fn joins_answers_in_order_and_leaves() {
+        let homeserver = Arc::new(Homeserver::default());
+        let (shutdown, task) = start(&homeserver).await;
+        let left = tokio::time::timeout(Duration::from_secs(5), async {
+            while !homeserver
+                .requests()
+                .iter()
+                .any(|(_, path, _)| path.ends_with("/leave"))
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(left.is_ok(), "never left, got {:?}", homeserver.requests());
+
+        let requests = homeserver.requests();
+        let room = format!("/_matrix/client/v3/rooms/{}", ROOM_ID);
+        let joined = requests
+            .iter()
+            .position(|(method, path, _)| method == "POST" && *path == format!("{}/join", room));
+        let sent = requests
+            .iter()
+            .filter(|(method, path, _)| {
+                method == "PUT" && path.starts_with(&format!("{}/send/", room))
+            })
+            .map(|(_, _, body)| body["body"].as_str().unwrap_or_default().to_owned())
+            .collect::<Vec<_>>();
+        let left = requests
+            .iter()
+            .position(|(method, path, _)| method == "POST" && *path == format!("{}/leave", room));
+        assert!(joined.is_some() && joined < left, "{:?}", requests);
+        let bot = bot();
+        assert_eq!(
+            sent[..2],
+            [
+                bot.poem_by_number("5").unwrap().to_string(),
+                bot.poem_by_number("6").unwrap().to_string(),
+            ]
+        );
+
+        shutdown.send(true).unwrap();
+        let stopped = tokio::time::timeout(Duration::from_secs(5), task).await;
+        assert!(matches!(stopped, Ok(Ok(()))));
+    }
+
+    #[tokio::test]
+    async fn stops_while_waiting_to_sync_again() {
+        let homeserver = Arc::new(Homeserver {
+            fail_sync: true,
+            ..Homeserver::default()
+        });
+        let (shutdown, task) = start(&homeserver).await;
+        // let it fail a sync and start waiting
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        shutdown.send(true).unwrap();
+        let stopped = tokio::time::timeout(Duration::from_secs(2), task).await;
+        assert!(matches!(stopped, Ok(Ok(()))));
+    }
+}
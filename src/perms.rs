@@ -0,0 +1,278 @@
+//! Who can run which command.
+//!
+//! Platforms give the author of a message a [`Level`] from their own
+//! permissions (server owner, administrator, ...). On top of that, a server
+//! can make users or roles bot admins, and change the level a command needs
+//! with [`PermsData::overrides`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use smol_str::SmolStr;
+
+use crate::{
+    command::{Command, Permission},
+    namespaced, Bot, BotError, Handler,
+};
+
+/// How much a user is trusted with, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum Level {
+    User,
+    Moderator,
+    Admin,
+    Owner,
+}
+
+impl Level {
+    pub const ALL: [Level; 4] = [Level::User, Level::Moderator, Level::Admin, Level::Owner];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::User => "user",
+            Level::Moderator => "moderator",
+            Level::Admin => "admin",
+            Level::Owner => "owner",
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mod" => Ok(Level::Moderator),
+            _ => Level::ALL
+                .into_iter()
+                .find(|level| level.name() == s)
+                .ok_or(()),
+        }
+    }
+}
+
+/// Permissions of a server, or of a channel outside servers.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct PermsData {
+    /// Users made bot admins, by their id on the platform.
+    #[serde(default)]
+    pub admins: BTreeSet<SmolStr>,
+    /// Roles whose members are bot admins.
+    #[serde(default)]
+    pub admin_roles: BTreeSet<SmolStr>,
+    /// Level needed to run a command (`listen`) or a subcommand
+    /// (`listen prob`) instead of the one it declares. One for a command also
+    /// applies to its subcommands that don't have their own.
+    #[serde(default)]
+    pub overrides: BTreeMap<SmolStr, Level>,
+}
+
+/// Accepts role mentions (`<@&id>`) as well as plain ids.
+fn parse_role_id(arg: &str) -> SmolStr {
+    arg.strip_prefix("<@&")
+        .and_then(|id| id.strip_suffix('>'))
+        .unwrap_or(arg)
+        .into()
+}
+
+impl Bot {
+    /// The permission needed to run `command` with `args` in `context_id`,
+    /// with its overrides applied, and whether arguments were passed.
+    pub fn permission_for(
+        &self,
+        context_id: &str,
+        command: &dyn Command,
+        args: &[&str],
+    ) -> (Permission, bool) {
+        let (permission, has_args) = command.permission_for(args);
+        let perms = match self.data.perms.get(context_id) {
+            Some(perms) => perms,
+            None => return (permission, has_args),
+        };
+        let sub = args.first().and_then(|name| command.subcommand(name));
+        let level = sub
+            .and_then(|sub| {
+                perms
+                    .overrides
+                    .get(format!("{} {}", command.name(), sub.name).as_str())
+            })
+            .or_else(|| perms.overrides.get(command.name()));
+        match level {
+            Some(level) => (permission.with_level(*level), has_args),
+            None => (permission, has_args),
+        }
+    }
+
+    /// Whether `author` runs the bot, which makes them owner everywhere.
+    pub fn is_owner(&self, platform: &str, author: &str) -> bool {
+        let id = namespaced(platform, author);
+        self.config().owners.contains(&id)
+    }
+
+    /// The level of the author of `handler` in `context_id`: whatever the
+    /// platform gives them, admin if they were made a bot admin, or owner if
+    /// they run the bot.
+    pub async fn author_level<E: Error>(
+        &self,
+        context_id: &str,
+        handler: &dyn Handler<Error = E>,
+    ) -> Result<Level, BotError<E>> {
+        if self.is_owner(handler.platform(), handler.author()) {
+            return Ok(Level::Owner);
+        }
+        let level = handler.author_level().await?;
+        if level >= Level::Admin {
+            return Ok(level);
+        }
+        let roles = handler.author_roles().await?;
+        let granted = self.data.perms.get(context_id).is_some_and(|perms| {
+            perms.admins.contains(handler.author())
+                || roles.iter().any(|role| perms.admin_roles.contains(role))
+        });
+        Ok(if granted { Level::Admin } else { level })
+    }
+
+    pub fn perms_show(&self, context_id: &str) -> SmolStr {
+        let perms = match self.data.perms.get(context_id) {
+            Some(perms) => perms.clone(),
+            None => PermsData::default(),
+        };
+        let list = |ids: &BTreeSet<SmolStr>| {
+            if ids.is_empty() {
+                "none".to_owned()
+            } else {
+                ids.iter()
+                    .map(|id| format!("`{}`", id))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        };
+        let mut text = format!(
+            "bot admins: {}\nadmin roles: {}",
+            list(&perms.admins),
+            list(&perms.admin_roles)
+        );
+        if perms.overrides.is_empty() {
+            text.push_str("\nno command levels were changed");
+        } else {
+            for (command, level) in &perms.overrides {
+                text.push_str(&format!("\n`{}` needs {}", command, level));
+            }
+        }
+        text.into()
+    }
+
+    /// Makes the user `user` a bot admin, or not anymore.
+    pub fn perms_set_admin(&self, context_id: &str, user: &str, admin: bool) -> SmolStr {
+        let user = crate::parse_user_id(user);
+        let mut perms = self.data.perms_entry(context_id);
+        match (admin, perms.admins.contains(&user)) {
+            (true, false) => {
+                perms.admins.insert(user.clone());
+                format!("`{}` is a bot admin now", user).into()
+            }
+            (false, true) => {
+                perms.admins.remove(&user);
+                format!("`{}` isn't a bot admin anymore", user).into()
+            }
+            (true, true) => format!("`{}` is a bot admin already", user).into(),
+            (false, false) => format!("`{}` isn't a bot admin", user).into(),
+        }
+    }
+
+    /// Makes the members of the role `role` bot admins, or not anymore.
+    pub fn perms_set_admin_role(&self, context_id: &str, role: &str, admin: bool) -> SmolStr {
+        let role = parse_role_id(role);
+        let mut perms = self.data.perms_entry(context_id);
+        match (admin, perms.admin_roles.contains(&role)) {
+            (true, false) => {
+                perms.admin_roles.insert(role.clone());
+                format!("members of `{}` are bot admins now", role).into()
+            }
+            (false, true) => {
+                perms.admin_roles.remove(&role);
+                format!("members of `{}` aren't bot admins anymore", role).into()
+            }
+            (true, true) => format!("members of `{}` are bot admins already", role).into(),
+            (false, false) => format!("members of `{}` aren't bot admins", role).into(),
+        }
+    }
+
+    /// Changes the level needed to run `command` (`name` or `name
+    /// subcommand`), or goes back to the one it declares if `level` is `None`.
+    pub fn perms_set_level(
+        &self,
+        context_id: &str,
+        command: &[&str],
+        level: Option<&str>,
+    ) -> Result<SmolStr, BotError> {
+        let key = self.override_key(command)?;
+        let name = key.split(' ').next().unwrap_or_default();
+        let locked = self
+            .commands()
+            .get(name)
+            .is_some_and(|command| command.permission() == Permission::BotOwner);
+        if name == "perms" || locked {
+            return Ok(SmolStr::new_inline("nice try"));
+        }
+        let level = match level.map(str::parse::<Level>) {
+            Some(Ok(level)) => Some(level),
+            Some(Err(())) => {
+                return Err(BotError::InvalidArgument(
+                    format!(
+                        "the level has to be one of {}",
+                        Level::ALL.map(|level| format!("`{}`", level)).join(", ")
+                    )
+                    .into(),
+                ))
+            }
+            None => None,
+        };
+        let mut perms = self.data.perms_entry(context_id);
+        Ok(match level {
+            Some(level) => {
+                perms.overrides.insert(key.clone(), level);
+                format!("`{}` needs {} now", key, level).into()
+            }
+            None if perms.overrides.remove(&key).is_some() => {
+                format!("`{}` needs what it used to again", key).into()
+            }
+            None => format!("the level of `{}` wasn't changed", key).into(),
+        })
+    }
+
+    /// `name` or `name subcommand` of an existing command, with aliases
+    /// resolved.
+    fn override_key(&self, command: &[&str]) -> Result<SmolStr, BotError> {
+        let (name, rest) = command
+            .split_first()
+            .ok_or_else(|| BotError::InvalidArgument("put a command".into()))?;
+        let found = self
+            .commands()
+            .get(name)
+            .ok_or_else(|| BotError::NotFound(format!("command called `{}`", name).into()))?;
+        match rest {
+            [] => Ok(found.name().into()),
+            [sub] => match found.subcommand(sub) {
+                Some(sub) => Ok(format!("{} {}", found.name(), sub.name).into()),
+                None => Err(BotError::NotFound(
+                    format!("subcommand `{}` of `{}`", sub, found.name()).into(),
+                )),
+            },
+            _ => Err(BotError::InvalidArgument(
+                "only commands and their subcommands have levels".into(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! Keeps the bot from being made to spam.
+//!
+//! Every user, channel and server has a token bucket. A command takes a token
+//! from the buckets of its author, channel and server, and can't run while one
+//! of them is empty. Messages the bot sends on its own (markov replies and
+//! insults) only take from the buckets of the channel and server. On top of
+//! that, commands can have a cooldown per user. Bot owners aren't limited.
+
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use smol_str::SmolStr;
+use tokio::time::Instant;
+
+use crate::{command::Command, namespaced, Bot};
+
+/// Checks between forgetting buckets that are full again and cooldowns that
+/// are over.
+const PRUNE_EVERY: usize = 1024;
+
+/// A bucket of `burst` tokens, refilled by `per_minute` tokens a minute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl RateLimit {
+    /// How long it takes to refill `tokens` tokens.
+    fn refill_time(self, tokens: f64) -> Duration {
+        Duration::from_secs_f64(tokens.max(0.0) * 60.0 / self.per_minute as f64)
+    }
+}
+
+/// `burst/per_minute`, like `5/10`.
+impl FromStr for RateLimit {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (burst, per_minute) = s.split_once('/').ok_or(())?;
+        Ok(Self {
+            burst: burst.trim().parse().map_err(|_| ())?,
+            per_minute: per_minute.trim().parse().map_err(|_| ())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Scope {
+    User,
+    Channel,
+    Guild,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When it's full again if nothing is taken from it.
+    full_at: Instant,
+}
+
+impl Bucket {
+    fn tokens(&self, limit: RateLimit, now: Instant) -> f64 {
+        let refilled = now.duration_since(self.updated).as_secs_f64() * limit.per_minute as f64;
+        (self.tokens + refilled / 60.0).min(limit.burst as f64)
+    }
+}
+
+/// Whether something can be done now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    /// Not for this long.
+    Limited(Duration),
+    /// Not yet, and the author was already told to slow down.
+    Ignored,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: DashMap<(Scope, SmolStr), Bucket>,
+    /// When a user can run a command again, by namespaced user id and command
+    /// name.
+    cooldowns: DashMap<(SmolStr, &'static str), Instant>,
+    /// Until when users were told to slow down, so they're only told once.
+    warned: DashMap<SmolStr, Instant>,
+    checks: AtomicUsize,
+}
+
+impl RateLimiter {
+    /// Takes a token from the bucket of every key in `limits` if all of them
+    /// have one, otherwise returns how long until they do.
+    fn take(&self, limits: &[(Scope, &str, RateLimit)], now: Instant) -> Result<(), Duration> {
+        let mut wait = Duration::ZERO;
+        let mut taken = Vec::with_capacity(limits.len());
+        for &(scope, key, limit) in limits {
+            // checked and taken under the same lock, so two messages can't
+            // both take the last token
+            let mut bucket = self.buckets.entry((scope, key.into())).or_insert(Bucket {
+                tokens: limit.burst as f64,
+                updated: now,
+                full_at: now,
+            });
+            let tokens = bucket.tokens(limit, now);
+            if tokens < 1.0 {
+                wait = wait.max(limit.refill_time(1.0 - tokens));
+                continue;
+            }
+            bucket.tokens = tokens - 1.0;
+            bucket.updated = now;
+            bucket.full_at = now + limit.refill_time(limit.burst as f64 - bucket.tokens);
+            taken.push((scope, key, limit));
+        }
+        if wait.is_zero() {
+            return Ok(());
+        }
+        // another bucket was empty, so these weren't used after all
+        for (scope, key, limit) in taken {
+            if let Some(mut bucket) = self.buckets.get_mut(&(scope, key.into())) {
+                bucket.tokens = (bucket.tokens + 1.0).min(limit.burst as f64);
+                bucket.full_at =
+                    bucket.updated + limit.refill_time(limit.burst as f64 - bucket.tokens);
+            }
+        }
+        Err(wait)
+    }
+
+    /// Forgets what doesn't limit anything anymore every [`PRUNE_EVERY`]
+    /// checks, so it doesn't grow forever.
+    fn maybe_prune(&self, now: Instant) {
+        if self.checks.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY != 0 {
+            return;
+        }
+        self.buckets.retain(|_, bucket| bucket.full_at > now);
+        self.cooldowns.retain(|_, until| *until > now);
+        self.warned.retain(|_, until| *until > now);
+    }
+}
+
+impl Bot {
+    /// The buckets of `channel_id` and `guild_id` that are limited.
+    fn place_limits<'a>(
+        &self,
+        channel_id: &'a str,
+        guild_id: Option<&'a str>,
+    ) -> Vec<(Scope, &'a str, RateLimit)> {
+        let mut limits = Vec::with_capacity(3);
+        if let Some(limit) = self.config.channel_rate_limit {
+            limits.push((Scope::Channel, channel_id, limit));
+        }
+        if let (Some(limit), Some(guild_id)) = (self.config.guild_rate_limit, guild_id) {
+            limits.push((Scope::Guild, guild_id, limit));
+        }
+        limits
+    }
+
+    /// Whether `author` can run `command` in `channel_id` now, which uses up
+    /// a token of every bucket it's limited by and starts its cooldown. Only
+    /// ask once they're allowed to run it.
+    pub fn limit_command(
+        &self,
+        platform: &str,
+        author: &str,
+        channel_id: &str,
+        guild_id: Option<&str>,
+        command: &dyn Command,
+    ) -> Verdict {
+        if self.is_owner(platform, author) {
+            return Verdict::Allowed;
+        }
+        let limiter = &self.rate_limiter;
+        let now = Instant::now();
+        limiter.maybe_prune(now);
+        let user_id = namespaced(platform, author);
+        // held until the cooldown is started, so it can only be started once
+        let mut cooldown = self.config.cooldowns.get(command.name()).map(|&secs| {
+            let until = limiter
+                .cooldowns
+                .entry((user_id.clone(), command.name()))
+                .or_insert(now);
+            (until, Duration::from_secs(secs))
+        });
+        let cooling_down = cooldown.as_ref().map_or(Duration::ZERO, |(until, _)| {
+            until.saturating_duration_since(now)
+        });
+        let mut limits = self.place_limits(channel_id, guild_id);
+        if let Some(limit) = self.config.user_rate_limit {
+            limits.push((Scope::User, &user_id, limit));
+        }
+        let taken = if cooling_down.is_zero() {
+            limiter.take(&limits, now)
+        } else {
+            Err(cooling_down)
+        };
+        match taken {
+            Ok(()) => {
+                if let Some((until, secs)) = &mut cooldown {
+                    **until = now + *secs;
+                }
+                Verdict::Allowed
+            }
+            Err(wait) => match limiter.warned.insert(user_id, now + wait) {
+                Some(until) if until > now => Verdict::Ignored,
+                _ => Verdict::Limited(wait),
+            },
+        }
+    }
+
+    /// Whether the bot can send a message on its own in `channel_id` now,
+    /// which uses up a token of the channel and server buckets.
+    pub fn limit_message(&self, channel_id: &str, guild_id: Option<&str>) -> bool {
+        let now = Instant::now();
+        self.rate_limiter.maybe_prune(now);
+        self.rate_limiter
+            .take(&self.place_limits(channel_id, guild_id), now)
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        burst: 2,
+        per_minute: 1,
+    };
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "5/10".parse(),
+            Ok(RateLimit {
+                burst: 5,
+                per_minute: 10
+            })
+        );
+        assert_eq!(
+            " 5 / 10 ".parse(),
+            Ok(RateLimit {
+                burst: 5,
+                per_minute: 10
+            })
+        );
+        assert_eq!("5".parse::<RateLimit>(), Err(()));
+        assert_eq!("5/-1".parse::<RateLimit>(), Err(()));
+    }
+
+    #[test]
+    fn takes_from_every_bucket_or_none() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        let one = RateLimit { burst: 1, ..LIMIT };
+        let user_in = |channel| [(Scope::User, "user", LIMIT), (Scope::Channel, channel, one)];
+        assert_eq!(limiter.take(&user_in("a"), now), Ok(()));
+        // the channel is empty, so the user keeps their token
+        assert_eq!(
+            limiter.take(&user_in("a"), now),
+            Err(Duration::from_secs(60))
+        );
+        assert_eq!(limiter.take(&user_in("b"), now), Ok(()));
+        assert_eq!(
+            limiter.take(&user_in("c"), now),
+            Err(Duration::from_secs(60))
+        );
+        assert_eq!(
+            limiter.take(&user_in("c"), now + Duration::from_secs(30)),
+            Err(Duration::from_secs(30))
+        );
+        assert_eq!(
+            limiter.take(&user_in("c"), now + Duration::from_secs(60)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn concurrent_takes() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        let limit = RateLimit {
+            burst: 100,
+            per_minute: 1,
+        };
+        let allowed = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        if limiter.take(&[(Scope::Guild, "guild", limit)], now).is_ok() {
+                            allowed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(allowed.into_inner(), 100);
+    }
+
+    #[test]
+    fn prunes_full_buckets() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        limiter.take(&[(Scope::User, "user", LIMIT)], now).unwrap();
+        limiter.maybe_prune(now);
+        assert_eq!(limiter.buckets.len(), 1);
+        limiter.checks.store(PRUNE_EVERY, Ordering::Relaxed);
+        limiter.maybe_prune(now + Duration::from_secs(60));
+        assert!(limiter.buckets.is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+//! Poems and insults the bot picks from.
+//!
+//! The built in ones are used unless `resources_dir` is configured, in which
+//! case `poems.txt` and `insults.txt` in it are used instead of them. Entries
+//! are separated by `-` in both files. They can be reloaded while the bot
+//! runs with `owner reload`, which also rebuilds the poem search index.
+
+use std::{io, path::Path};
+
+use rand::{seq::IteratorRandom, Rng};
+
+use crate::{chain::Chain, search::PoemIndex, tokenizer, MChain, INSULTS, POEMS};
+
+#[derive(Debug)]
+pub struct Resources {
+    poems: String,
+    insults: String,
+    /// Chain fed every poem, to generate new ones.
+    poem_chain: MChain,
+    poem_index: PoemIndex,
+}
+
+impl Resources {
+    pub fn builtin() -> Self {
+        Self::new(POEMS.to_owned(), INSULTS.to_owned())
+    }
+
+    fn new(poems: String, insults: String) -> Self {
+        let mut poem_chain = Chain::new();
+        poem_chain.feed(tokenizer::tokenize(&poems.replace('-', "")));
+        let poem_index = PoemIndex::new(poems.split('-'));
+        Self {
+            poems,
+            insults,
+            poem_chain,
+            poem_index,
+        }
+    }
+
+    /// Reads the files in `dir`, using the built in resource for any of them
+    /// that's missing.
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let read = |name: &str, builtin: &str| -> io::Result<String> {
+            let path = dir.join(name);
+            let text = match std::fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => builtin.to_owned(),
+                Err(err) => return Err(err),
+            };
+            if text.split('-').all(|entry| entry.trim().is_empty()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("`{}` has nothing in it", path.display()),
+                ));
+            }
+            Ok(text)
+        };
+        Ok(Self::new(
+            read("poems.txt", POEMS)?,
+            read("insults.txt", INSULTS)?,
+        ))
+    }
+
+    pub fn poems(&self) -> impl Iterator<Item = &str> {
+        self.poems.split('-')
+    }
+
+    pub fn insults(&self) -> impl Iterator<Item = &str> {
+        self.insults.split('-')
+    }
+
+    /// The poem numbered `number`, counting from 1.
+    pub fn poem(&self, number: usize) -> Option<&str> {
+        self.poems().nth(number.checked_sub(1)?)
+    }
+
+    pub fn poem_chain(&self) -> &MChain {
+        &self.poem_chain
+    }
+
+    pub fn poem_index(&self) -> &PoemIndex {
+        &self.poem_index
+    }
+
+    pub fn random_poem(&self, rng: &mut impl Rng) -> &str {
+        self.poems().choose(rng).expect("always something in poems")
+    }
+
+    pub fn random_insult(&self, rng: &mut impl Rng) -> &str {
+        self.insults()
+            .choose(rng)
+            .expect("always something in insults")
+    }
+}
@@ -0,0 +1,255 @@
+//! Runs every enabled platform adapter on top of a single shared [`Bot`].
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::watch, task::JoinSet};
+
+use crate::{
+    config::Config,
+    namespaced, perr,
+    storage::{self, FileStorage, Storage},
+    Bot, BotData, AUTO_SAVE_RETRY_DELAY,
+};
+
+/// Save files from before adapters shared one [`Bot`], with the platform their ids belong to.
+const LEGACY_DATA_PATHS: &[(&str, &str)] = &[
+    ("discord", "data_discord"),
+    ("cli", "data_cli"),
+    ("matrix", "data_matrix"),
+];
+const SHUTDOWN_TIMEOUT: u64 = 10;
+const FINAL_SAVE_ATTEMPTS: u32 = 3;
+
+/// Lets adapters know when the bot is shutting down.
+#[derive(Debug, Clone)]
+pub struct Shutdown(pub(crate) watch::Receiver<bool>);
+
+impl Shutdown {
+    /// Resolves once shutdown has been requested.
+    pub async fn wait(&mut self) {
+        while !*self.0.borrow() {
+            if self.0.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Names of the adapters compiled into this binary.
+pub fn available_adapters() -> Vec<&'static str> {
+    let mut adapters = Vec::new();
+    if cfg!(feature = "discord") {
+        adapters.push("discord");
+    }
+    if cfg!(feature = "cli") {
+        adapters.push("cli");
+    }
+    if cfg!(feature = "matrix") {
+        adapters.push("matrix");
+    }
+    adapters
+}
+
+fn spawn_adapter(tasks: &mut JoinSet<()>, name: &str, bot: Bot, shutdown: Shutdown) -> bool {
+    match name {
+        #[cfg(feature = "discord")]
+        "discord" => {
+            tasks.spawn(crate::discord::run(bot, shutdown));
+            true
+        }
+        #[cfg(feature = "cli")]
+        "cli" => {
+            tasks.spawn(crate::cli::run(bot, shutdown));
+            true
+        }
+        #[cfg(feature = "matrix")]
+        "matrix" => {
+            tasks.spawn(crate::matrix::run(bot, shutdown));
+            true
+        }
+        // everything is unused without any adapter compiled in
+        _ => {
+            let _ = (tasks, bot, shutdown);
+            false
+        }
+    }
+}
+
+/// Loads the bot from `storage`, importing legacy save files if there is none yet.
+///
+/// Returns `None` if there is data but it couldn't be read, since starting
+/// with an empty bot would overwrite it on the next save.
+async fn load_bot(storage: Arc<dyn Storage>, config: Arc<Config>) -> Option<Bot> {
+    match Bot::load(storage.clone(), config.clone()).await {
+        Ok(Some(bot)) => return Some(bot),
+        Ok(None) => {}
+        Err(err) => {
+            tracing::error!("couldnt load bot data: {}", err);
+            return None;
+        }
+    }
+
+    let bot = Bot::new(storage, config);
+    for (platform, legacy_path) in LEGACY_DATA_PATHS {
+        match FileStorage::new(legacy_path).load().await {
+            Ok(Some(legacy)) => {
+                tracing::info!(
+                    "importing {} data from `{}`, it can be removed after the next save",
+                    platform,
+                    legacy_path
+                );
+                import_legacy(&bot, platform, legacy);
+            }
+            Ok(None) => {}
+            Err(err) => tracing::error!("couldnt import `{}`: {}", legacy_path, err),
+        }
+    }
+    bot.data.mark_all_changed();
+    Some(bot)
+}
+
+/// Moves data saved by a single adapter into `bot`, namespacing its ids.
+fn import_legacy(bot: &Bot, platform: &str, legacy: BotData) {
+    for (id, data) in legacy.mchain {
+        bot.data.mchain.insert(namespaced(platform, &id), data);
+    }
+    for (id, data) in legacy.insult_data {
+        bot.data.insult_data.insert(namespaced(platform, &id), data);
+    }
+    for (id, prefix) in legacy.prefix {
+        bot.data.prefix.insert(namespaced(platform, &id), prefix);
+    }
+    for (id, user) in legacy.users {
+        bot.data.users.insert(namespaced(platform, &id), user);
+    }
+}
+
+/// Opens the configured storage backend.
+fn open_storage(config: &Config) -> Option<Arc<dyn Storage>> {
+    match storage::open(&config.storage, config.data_path()) {
+        Ok(storage) => Some(storage),
+        Err(err) => {
+            tracing::error!("couldnt open storage: {}", err);
+            None
+        }
+    }
+}
+
+/// Starts the configured adapters, or every available adapter if none are
+/// configured, and runs until all of them stop or the process is interrupted.
+pub async fn run(config: Config) {
+    let adapters = if config.adapters.is_empty() {
+        available_adapters().into_iter().map(String::from).collect()
+    } else {
+        config.adapters.clone()
+    };
+
+    let config = Arc::new(config);
+    let bot = match open_storage(&config) {
+        Some(storage) => match load_bot(storage, config.clone()).await {
+            Some(bot) => bot,
+            None => return,
+        },
+        None => return,
+    };
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut tasks = JoinSet::new();
+    for name in &adapters {
+        if spawn_adapter(&mut tasks, name, bot.clone(), Shutdown(shutdown_rx.clone())) {
+            tracing::info!("started {} adapter", name);
+        } else {
+            tracing::error!(
+                "unknown adapter `{}`, available adapters are: {:?}",
+                name,
+                available_adapters()
+            );
+        }
+    }
+    if tasks.is_empty() {
+        tracing::error!("no adapters to run");
+        return;
+    }
+
+    bot.start_autosave_task(config.autosave_period());
+
+    let wait_adapters = async {
+        while let Some(res) = tasks.join_next().await {
+            perr!(res);
+        }
+    };
+    tokio::select! {
+        _ = wait_adapters => {}
+        _ = tokio::signal::ctrl_c() => {
+            tracing::info!("shutting down");
+        }
+    }
+
+    let _ = shutdown_tx.send(true);
+    let finish = async {
+        while let Some(res) = tasks.join_next().await {
+            perr!(res);
+        }
+    };
+    if tokio::time::timeout(Duration::from_secs(SHUTDOWN_TIMEOUT), finish)
+        .await
+        .is_err()
+    {
+        tracing::warn!("adapters didn't stop in time, aborting them");
+        tasks.abort_all();
+    }
+
+    final_save(&bot).await;
+}
+
+/// Rebuilds the markov chains of `channels` (every channel if empty) and of
+/// the servers they're in from the corpus, and saves them.
+pub async fn rebuild(config: Config, channels: Vec<String>) {
+    if config.corpus_dir.is_none() {
+        tracing::error!("no corpus directory is configured, there is nothing to rebuild from");
+        return;
+    }
+    let config = Arc::new(config);
+    let bot = match open_storage(&config) {
+        Some(storage) => match load_bot(storage, config.clone()).await {
+            Some(bot) => bot,
+            None => return,
+        },
+        None => return,
+    };
+
+    let channels = if channels.is_empty() {
+        bot.markov_channels()
+    } else {
+        channels.into_iter().map(Into::into).collect()
+    };
+    let mut rebuilt = Vec::with_capacity(channels.len());
+    for channel_id in channels {
+        match bot.markov_rebuild(&channel_id).await {
+            Ok(Some(count)) => {
+                tracing::info!("rebuilt `{}` from {} messages", channel_id, count);
+                rebuilt.push(channel_id);
+            }
+            Ok(None) => tracing::warn!("`{}` isn't listened to, skipping it", channel_id),
+            Err(err) => tracing::error!("couldnt rebuild `{}`: {}", channel_id, err),
+        }
+    }
+    // server chains learn from every channel of the server
+    bot.guilds_rebuild(&rebuilt).await;
+    final_save(&bot).await;
+}
+
+/// Flushes everything that changed since the last autosave, retrying a few times.
+async fn final_save(bot: &Bot) {
+    let mut delay = AUTO_SAVE_RETRY_DELAY;
+    for attempt in 1..=FINAL_SAVE_ATTEMPTS {
+        match bot.save().await {
+            Ok(()) => return,
+            Err(err) if attempt < FINAL_SAVE_ATTEMPTS => {
+                tracing::error!("couldnt save bot data, retrying in {:?}: {}", delay, err);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(err) => tracing::error!("couldnt save bot data, giving up: {}", err),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Full text search over the poems.
+//!
+//! Poems are split into words, which are lowercased and stemmed so "cried"
+//! finds "cry" and "searching" finds "search". Poems are ranked with BM25, so
+//! rare words count more than common ones and short poems more than long
+//! ones. Words in quotes have to appear together, in that order.
+
+use std::collections::HashMap;
+
+use smol_str::SmolStr;
+
+/// How much a word appearing again in a poem adds to its score.
+const K1: f64 = 1.2;
+/// How much longer poems are penalized.
+const B: f64 = 0.75;
+
+#[derive(Debug, Default)]
+pub struct PoemIndex {
+    /// Stemmed words of every poem, in order.
+    poems: Vec<Vec<SmolStr>>,
+    /// Poems a stemmed word is in, and how many times.
+    postings: HashMap<SmolStr, Vec<(usize, u32)>>,
+    average_len: f64,
+}
+
+/// A poem matching a search, by its index in the poems.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub index: usize,
+    pub score: f64,
+}
+
+impl PoemIndex {
+    pub fn new<'a>(poems: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut index = Self::default();
+        for (i, poem) in poems.into_iter().enumerate() {
+            let words = words(poem).collect::<Vec<_>>();
+            let mut counts = HashMap::<_, u32>::new();
+            for word in &words {
+                *counts.entry(word.clone()).or_default() += 1;
+            }
+            for (word, count) in counts {
+                index.postings.entry(word).or_default().push((i, count));
+            }
+            index.poems.push(words);
+        }
+        let total = index.poems.iter().map(Vec::len).sum::<usize>();
+        index.average_len = total as f64 / index.poems.len().max(1) as f64;
+        index
+    }
+
+    /// Poems matching `query`, best first. Poems have to contain every quoted
+    /// phrase and at least one of the words.
+    pub fn search(&self, query: &str) -> Vec<Hit> {
+        let (words, phrases) = parse_query(query);
+        let mut scores = HashMap::<usize, f64>::new();
+        for word in words.iter().chain(phrases.iter().flatten()) {
+            let postings = match self.postings.get(word) {
+                Some(postings) => postings,
+                None => continue,
+            };
+            let count = self.poems.len() as f64;
+            let containing = postings.len() as f64;
+            let idf = (1.0 + (count - containing + 0.5) / (containing + 0.5)).ln();
+            for &(index, frequency) in postings {
+                let frequency = frequency as f64;
+                let len = self.poems[index].len() as f64 / self.average_len;
+                let score = idf * frequency * (K1 + 1.0) / (frequency + K1 * (1.0 - B + B * len));
+                *scores.entry(index).or_default() += score;
+            }
+        }
+        let mut hits = scores
+            .into_iter()
+            .filter(|(index, _)| {
+                phrases
+                    .iter()
+                    .all(|phrase| contains_phrase(&self.poems[*index], phrase))
+            })
+            .map(|(index, score)| Hit { index, score })
+            .collect::<Vec<_>>();
+        hits.sort_unstable_by(|a, b| b.score.total_cmp(&a.score).then(a.index.cmp(&b.index)));
+        hits
+    }
+}
+
+/// Whether `text` has a word of `query` in it, like a line of a poem it found.
+pub fn has_match(text: &str, query: &str) -> bool {
+    let (lone, phrases) = parse_query(query);
+    words(text)
+        .any(|word| lone.contains(&word) || phrases.iter().any(|phrase| phrase.contains(&word)))
+}
+
+/// Lone words and quoted phrases of `query`, stemmed.
+fn parse_query(query: &str) -> (Vec<SmolStr>, Vec<Vec<SmolStr>>) {
+    let (mut lone, mut phrases) = (Vec::new(), Vec::new());
+    // every other part is between quotes
+    for (i, part) in query.split(['"', '“', '”']).enumerate() {
+        if i % 2 == 0 {
+            lone.extend(words(part));
+        } else {
+            let phrase = words(part).collect::<Vec<_>>();
+            if !phrase.is_empty() {
+                phrases.push(phrase);
+            }
+        }
+    }
+    (lone, phrases)
+}
+
+fn contains_phrase(words: &[SmolStr], phrase: &[SmolStr]) -> bool {
+    words.windows(phrase.len()).any(|window| window == phrase)
+}
+
+/// The lowercased, stemmed words of `text`, without punctuation.
+pub fn words(text: &str) -> impl Iterator<Item = SmolStr> + '_ {
+    text.split(|ch: char| !ch.is_alphanumeric() && !matches!(ch, '\'' | '’' | '‘'))
+        .map(|word| {
+            word.chars()
+                .filter(|ch| ch.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .map(|word| stem(&word))
+}
+
+/// Strips the common English suffixes off `word`, so different forms of a
+/// word end up the same. It doesn't have to be a real word, only the same for
+/// every form.
+fn stem(word: &str) -> SmolStr {
+    if word.len() <= 3 || !word.is_ascii() {
+        return word.into();
+    }
+    let mut stem = word.to_owned();
+    if let Some(rest) = stem.strip_suffix("sses") {
+        stem = format!("{}ss", rest);
+    } else if let Some(rest) = stem
+        .strip_suffix("ies")
+        .or_else(|| stem.strip_suffix("ied"))
+    {
+        // "cry" from "cries" and "cried"
+        return format!("{}y", rest).into();
+    } else if stem.ends_with('s') && !stem.ends_with("ss") && !stem.ends_with("us") {
+        stem.pop();
+    }
+    for suffix in ["ing", "ed", "ly"] {
+        match stem.strip_suffix(suffix) {
+            // "cry" from "crying", but not "th" from "thing"
+            Some(rest) if rest.len() >= 3 && rest.contains(|ch| is_vowel(ch) || ch == 'y') => {
+                stem.truncate(rest.len());
+                break;
+            }
+            _ => {}
+        }
+    }
+    let bytes = stem.as_bytes();
+    let n = bytes.len();
+    // "stop" from "stopped", which is "stopp" by now
+    if n >= 4 && bytes[n - 1] == bytes[n - 2] && !is_vowel(bytes[n - 1] as char) {
+        if !matches!(bytes[n - 1], b'l' | b's' | b'z') {
+            stem.pop();
+        }
+    } else if stem.len() > 3 && stem.ends_with('e') {
+        // "lov" from "love" and "loved"
+        stem.pop();
+    }
+    if stem.len() > 3 && stem.ends_with('i') {
+        // "happy" from "happily"
+        stem.pop();
+        stem.push('y');
+    }
+    stem.into()
+}
+
+fn is_vowel(ch: char) -> bool {
+    matches!(ch, 'a' | 'e' | 'i' | 'o' | 'u')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stems() {
+        for (word, expected) in [
+            // plurals
+            ("cries", "cry"),
+            ("classes", "class"),
+            ("class", "class"),
+            ("stops", "stop"),
+            ("virus", "virus"),
+            // -ing, -ed and -ly
+            ("crying", "cry"),
+            ("cried", "cry"),
+            ("stopped", "stop"),
+            ("stopping", "stop"),
+            ("love", "lov"),
+            ("loved", "lov"),
+            ("loves", "lov"),
+            ("happily", "happy"),
+            ("thing", "thing"),
+            ("sing", "sing"),
+            // short words
+            ("is", "is"),
+            ("was", "was"),
+            ("bed", "bed"),
+            ("cafés", "cafés"),
+        ] {
+            assert_eq!(stem(word), expected, "{}", word);
+        }
+    }
+
+    #[test]
+    fn words_are_lowercased_without_punctuation() {
+        assert_eq!(
+            words("Don't CRY, she cried!").collect::<Vec<_>>(),
+            ["dont", "cry", "she", "cry"]
+        );
+    }
+
+    #[test]
+    fn ranking() {
+        let index = PoemIndex::new([
+            "the cat sat",
+            "the dog sat on the mat with the cat",
+            "the dog ran",
+        ]);
+        let ranked = |query| {
+            index
+                .search(query)
+                .into_iter()
+                .map(|hit| hit.index)
+                .collect::<Vec<_>>()
+        };
+        // shorter poems first
+        assert_eq!(ranked("cats"), [0, 1]);
+        // rare words count more
+        assert_eq!(ranked("the ran")[0], 2);
+        // phrases have to be there, in order
+        assert_eq!(ranked("\"sat on\""), [1]);
+        assert_eq!(ranked("“on sat”"), [] as [usize; 0]);
+        assert_eq!(ranked("\"dog sat\" cat"), [1]);
+        assert_eq!(ranked("bird"), [] as [usize; 0]);
+        assert_eq!(ranked(""), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn matches() {
+        assert!(has_match("The cats sat.", "cat"));
+        assert!(has_match("Sat down", "\"cat sat\""));
+        assert!(!has_match("a dog", "cat"));
+    }
+}
@@ -0,0 +1,202 @@
+//! Persisting the bot's data.
+//!
+//! [`FileStorage`] keeps everything in a single file, while [`SqliteStorage`]
+//! (behind the `sqlite` feature) keeps every channel in its own row. Both go
+//! through the same migrations, so data saved by older versions still loads.
+//!
+//! [`BotData`] keeps track of which of its entries changed since the last
+//! save as [`Changes`], so backends that can write entries one by one only
+//! have to write those.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+    io,
+    path::Path,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use smol_str::SmolStr;
+
+use crate::BotData;
+
+pub use file::FileStorage;
+pub use migrations::CURRENT_VERSION;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+mod file;
+mod migrations;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[async_trait]
+pub trait Storage: Debug + Send + Sync {
+    /// Loads everything that was saved, or `None` if nothing was saved yet.
+    async fn load(&self) -> Result<Option<BotData>, StorageError>;
+
+    /// Saves `data`, of which only the entries in `changes` were changed
+    /// since the last save. Changed entries that aren't in `data` anymore were
+    /// removed.
+    async fn save(&self, data: &BotData, changes: &Changes) -> Result<(), StorageError>;
+}
+
+/// Keys of the [`BotData`] entries that were changed (or removed), per field.
+#[derive(Debug, Default, Clone)]
+pub struct Changes {
+    pub mchain: HashSet<SmolStr>,
+    pub insult_data: HashSet<SmolStr>,
+    pub prefix: HashSet<SmolStr>,
+    pub users: HashSet<SmolStr>,
+    pub guilds: HashSet<SmolStr>,
+    pub perms: HashSet<SmolStr>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.mchain.is_empty()
+            && self.insult_data.is_empty()
+            && self.prefix.is_empty()
+            && self.users.is_empty()
+            && self.guilds.is_empty()
+            && self.perms.is_empty()
+    }
+
+    pub fn extend(&mut self, other: Changes) {
+        self.mchain.extend(other.mchain);
+        self.insult_data.extend(other.insult_data);
+        self.prefix.extend(other.prefix);
+        self.users.extend(other.users);
+        self.guilds.extend(other.guilds);
+        self.perms.extend(other.perms);
+    }
+}
+
+/// Opens the storage backend called `kind` at `path`.
+pub fn open(kind: &str, path: &Path) -> Result<Arc<dyn Storage>, StorageError> {
+    match kind {
+        "file" => Ok(Arc::new(FileStorage::new(path))),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Ok(Arc::new(SqliteStorage::open(path)?)),
+        kind => Err(StorageError::UnknownBackend(kind.into())),
+    }
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    Decompress(lz4_flex::block::DecompressError),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    /// The data was written by a newer version of the bot.
+    UnsupportedVersion(u16),
+    UnknownCompression(u8),
+    /// Data couldn't be migrated from `version` to the next version.
+    Migration {
+        version: u16,
+        reason: String,
+    },
+    UnknownBackend(String),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl StorageError {
+    /// Whether this error means there was no save file at all.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, StorageError::Io(err) if err.kind() == io::ErrorKind::NotFound)
+    }
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(err) => write!(f, "io error: {}", err),
+            StorageError::Decompress(err) => write!(f, "couldnt decompress data: {}", err),
+            StorageError::Parse(err) => write!(f, "couldnt parse data: {}", err),
+            StorageError::Serialize(err) => write!(f, "couldnt serialize data: {}", err),
+            StorageError::UnsupportedVersion(version) => write!(
+                f,
+                "data is version {} but only versions up to {} are supported",
+                version, CURRENT_VERSION
+            ),
+            StorageError::UnknownCompression(kind) => {
+                write!(f, "unknown compression kind {}", kind)
+            }
+            StorageError::Migration { version, reason } => write!(
+                f,
+                "couldnt migrate data from version {}: {}",
+                version, reason
+            ),
+            StorageError::UnknownBackend(kind) => write!(f, "unknown storage backend `{}`", kind),
+            #[cfg(feature = "sqlite")]
+            StorageError::Sqlite(err) => write!(f, "sqlite error: {}", err),
+        }
+    }
+}
+
+impl Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        StorageError::Io(err)
+    }
+}
+
+impl From<lz4_flex::block::DecompressError> for StorageError {
+    fn from(err: lz4_flex::block::DecompressError) -> Self {
+        StorageError::Decompress(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for StorageError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        StorageError::Parse(err)
+    }
+}
+
+impl From<ron::Error> for StorageError {
+    fn from(err: ron::Error) -> Self {
+        StorageError::Serialize(err)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        StorageError::Sqlite(err)
+    }
+}
+
+/// Serializes `value` as lz4 compressed RON.
+fn compress<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, StorageError> {
+    let raw = ron::ser::to_string(value)?;
+    Ok(lz4_flex::compress_prepend_size(raw.as_bytes()))
+}
+
+/// Parses lz4 compressed RON written by [`compress`].
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+fn decompress<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StorageError> {
+    let raw = decompress_lz4(bytes)?;
+    Ok(ron::de::from_bytes(&raw)?)
+}
+
+/// Decompresses lz4 data prepended with its size, without trusting the size
+/// of corrupted data enough to allocate gigabytes for it.
+fn decompress_lz4(bytes: &[u8]) -> Result<Vec<u8>, StorageError> {
+    // lz4 can't compress better than this
+    const MAX_RATIO: usize = 255;
+    if let [a, b, c, d, payload @ ..] = bytes {
+        let size = u32::from_le_bytes([*a, *b, *c, *d]) as usize;
+        if size > payload.len().saturating_mul(MAX_RATIO) {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "compressed data is corrupted",
+            )));
+        }
+    }
+    Ok(lz4_flex::decompress_size_prepended(bytes)?)
+}
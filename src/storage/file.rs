@@ -0,0 +1,378 @@
+//! Keeps all bot data in a single lz4 compressed RON file, which is rewritten
+//! as a whole whenever anything changed.
+//!
+//! Saves are written to a temporary file which is synced and then renamed over
+//! the previous save, so a crash mid-write never leaves a half written file
+//! behind. The last [`BACKUP_COUNT`] saves are kept next to it as `<path>.1`
+//! (newest) to `<path>.N` (oldest), and are used when the save file itself
+//! can't be read.
+//!
+//! Every save starts with a header made of [`MAGIC`], the format version as a
+//! little endian `u16` and a byte for the [`Compression`] used on the rest of
+//! the file. Saves without a header are from before it was added, they are
+//! treated as version `0` compressed with lz4.
+
+use std::{
+    borrow::Cow,
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+use super::{migrations, Changes, Storage, StorageError, CURRENT_VERSION};
+use crate::BotData;
+
+pub const BACKUP_COUNT: usize = 3;
+pub const MAGIC: &[u8; 4] = b"BERN";
+const HEADER_LEN: usize = MAGIC.len() + 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Compression {
+    None = 0,
+    Lz4 = 1,
+}
+
+impl TryFrom<u8> for Compression {
+    type Error = StorageError;
+
+    fn try_from(kind: u8) -> Result<Self, Self::Error> {
+        match kind {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            kind => Err(StorageError::UnknownCompression(kind)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FileStorage {
+    path: PathBuf,
+    write_lock: Mutex<()>,
+}
+
+impl FileStorage {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            write_lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for FileStorage {
+    async fn load(&self) -> Result<Option<BotData>, StorageError> {
+        match read(&self.path).await {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.is_not_found() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn save(&self, data: &BotData, changes: &Changes) -> Result<(), StorageError> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let _guard = self.write_lock.lock().await;
+        write(&self.path, data).await
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    path.into()
+}
+
+fn backup_path(path: &Path, n: usize) -> PathBuf {
+    with_suffix(path, &format!(".{}", n))
+}
+
+fn encode(data: &BotData) -> Result<Vec<u8>, StorageError> {
+    let compressed = super::compress(data)?;
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + compressed.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+    bytes.push(Compression::Lz4 as u8);
+    bytes.extend_from_slice(&compressed);
+    Ok(bytes)
+}
+
+fn decode(bytes: &[u8]) -> Result<BotData, StorageError> {
+    let (version, compression, payload) = match bytes.strip_prefix(MAGIC) {
+        Some([v0, v1, kind, payload @ ..]) => (
+            u16::from_le_bytes([*v0, *v1]),
+            Compression::try_from(*kind)?,
+            payload,
+        ),
+        Some(_) => {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated header",
+            )))
+        }
+        None => (0, Compression::Lz4, bytes),
+    };
+    if version > CURRENT_VERSION {
+        return Err(StorageError::UnsupportedVersion(version));
+    }
+    let raw: Cow<[u8]> = match compression {
+        Compression::None => payload.into(),
+        Compression::Lz4 => super::decompress_lz4(payload)?.into(),
+    };
+    migrations::deserialize(version, &raw)
+}
+
+async fn read_file(path: &Path) -> Result<BotData, StorageError> {
+    let bytes = tokio::fs::read(path).await?;
+    decode(&bytes)
+}
+
+/// Reads the save file at `path`, falling back to the newest backup that can
+/// be read if it is missing or corrupted.
+async fn read(path: &Path) -> Result<BotData, StorageError> {
+    let mut first_err: Option<StorageError> = None;
+    let candidates =
+        std::iter::once(path.to_owned()).chain((1..=BACKUP_COUNT).map(|n| backup_path(path, n)));
+    for candidate in candidates {
+        match read_file(&candidate).await {
+            Ok(data) => {
+                if let Some(err) = first_err {
+                    tracing::warn!(
+                        "couldnt read `{}` ({}), loaded backup `{}` instead",
+                        path.display(),
+                        err,
+                        candidate.display()
+                    );
+                }
+                return Ok(data);
+            }
+            Err(err) if err.is_not_found() => {
+                first_err.get_or_insert(err);
+            }
+            Err(err) => {
+                tracing::error!("couldnt read `{}`: {}", candidate.display(), err);
+                // a corrupted file is more interesting than a missing one
+                match &first_err {
+                    Some(prev) if !prev.is_not_found() => {}
+                    _ => first_err = Some(err),
+                }
+            }
+        }
+    }
+    Err(first_err.expect("always at least one candidate"))
+}
+
+/// Atomically replaces the save file at `path`, rotating the old one into the backups.
+async fn write(path: &Path, data: &BotData) -> Result<(), StorageError> {
+    let bytes = encode(data)?;
+
+    let tmp_path = with_suffix(path, ".tmp");
+    let mut file = tokio::fs::File::create(&tmp_path).await?;
+    file.write_all(&bytes).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    for n in (1..BACKUP_COUNT).rev() {
+        rename_if_exists(&backup_path(path, n), &backup_path(path, n + 1)).await?;
+    }
+    if BACKUP_COUNT > 0 {
+        rename_if_exists(path, &backup_path(path, 1)).await?;
+    }
+    tokio::fs::rename(&tmp_path, path).await?;
+    sync_parent_dir(path).await
+}
+
+async fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match tokio::fs::rename(from, to).await {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+/// Makes sure the renames themselves survive a crash.
+async fn sync_parent_dir(path: &Path) -> Result<(), StorageError> {
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        tokio::fs::File::open(dir).await?.sync_all().await?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use smol_str::SmolStr;
+
+    use super::*;
+
+    /// A path nothing was saved to yet.
+    fn save_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bernbot-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        for n in 0..=BACKUP_COUNT {
+            let _ = std::fs::remove_file(backup_path(&path, n));
+        }
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn data(prefix: &str) -> BotData {
+        let data = BotData::default();
+        data.prefix.insert("discord:1".into(), prefix.into());
+        data
+    }
+
+    fn changes() -> Changes {
+        Changes {
+            prefix: ["discord:1".into()].into_iter().collect(),
+            ..Changes::default()
+        }
+    }
+
+    fn prefix(data: &BotData) -> SmolStr {
+        data.prefix.get("discord:1").unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let storage = FileStorage::new(save_path("round_trip"));
+        assert!(storage.load().await.unwrap().is_none());
+        storage.save(&data("!"), &changes()).await.unwrap();
+        let loaded = storage.load().await.unwrap().unwrap();
+        assert_eq!(prefix(&loaded), "!");
+        assert_eq!(loaded.mchain.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn nothing_changed_writes_nothing() {
+        let path = save_path("nothing_changed");
+        let storage = FileStorage::new(&path);
+        storage.save(&data("!"), &Changes::default()).await.unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_backup() {
+        let path = save_path("falls_back");
+        let storage = FileStorage::new(&path);
+        storage.save(&data("old"), &changes()).await.unwrap();
+        storage.save(&data("new"), &changes()).await.unwrap();
+        assert_eq!(prefix(&storage.load().await.unwrap().unwrap()), "new");
+
+        // cut off mid write
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        assert_eq!(prefix(&storage.load().await.unwrap().unwrap()), "old");
+
+        // garbage after a valid header
+        let mut corrupted = bytes[..HEADER_LEN].to_vec();
+        corrupted.extend_from_slice(&[0xff; 64]);
+        std::fs::write(&path, corrupted).unwrap();
+        assert_eq!(prefix(&storage.load().await.unwrap().unwrap()), "old");
+
+        std::fs::write(&path, &bytes[..2]).unwrap();
+        assert_eq!(prefix(&storage.load().await.unwrap().unwrap()), "old");
+    }
+
+    #[tokio::test]
+    async fn saves_rotate_backups() {
+        let path = save_path("rotate");
+        let storage = FileStorage::new(&path);
+        for prefix in ["1", "2", "3", "4", "5"] {
+            storage.save(&data(prefix), &changes()).await.unwrap();
+        }
+        let saves =
+            std::iter::once(path.clone()).chain((1..=BACKUP_COUNT).map(|n| backup_path(&path, n)));
+        for (save, expected) in saves.zip(["5", "4", "3", "2"]) {
+            assert_eq!(prefix(&read_file(&save).await.unwrap()), expected);
+        }
+        assert!(!backup_path(&path, BACKUP_COUNT + 1).exists());
+    }
+
+    #[tokio::test]
+    async fn every_backup_invalid() {
+        let path = save_path("every_backup_invalid");
+        let storage = FileStorage::new(&path);
+        for _ in 0..=BACKUP_COUNT {
+            storage.save(&data("!"), &changes()).await.unwrap();
+        }
+        for n in 1..=BACKUP_COUNT {
+            std::fs::write(backup_path(&path, n), b"not a save").unwrap();
+        }
+        std::fs::write(&path, b"BERN").unwrap();
+
+        // refuses to load anything instead of starting over and overwriting them
+        let err = storage.load().await.unwrap_err();
+        assert!(!err.is_not_found(), "{}", err);
+        assert!(matches!(err, StorageError::Io(_)), "{}", err);
+    }
+
+    fn with_header(version: u16, compression: Compression, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.push(compression as u8);
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn headerless_saves_are_v0() {
+        let bytes = lz4_flex::compress_prepend_size(migrations::tests::V0.as_bytes());
+        let data = decode(&bytes).unwrap();
+        assert_eq!(*data.prefix.get("10").unwrap(), "!");
+        assert!(data.mchain.get("10").unwrap().speak);
+    }
+
+    #[test]
+    fn headers() {
+        let v1 = r#"(mchain: {"discord:10": (enabled: true)})"#;
+        let data = decode(&with_header(1, Compression::None, v1.as_bytes())).unwrap();
+        let markov = data.mchain.get("discord:10").unwrap();
+        assert!(markov.learn && markov.speak);
+
+        let compressed = lz4_flex::compress_prepend_size(v1.as_bytes());
+        let data = decode(&with_header(1, Compression::Lz4, &compressed)).unwrap();
+        assert!(data.mchain.get("discord:10").unwrap().speak);
+
+        let mut unknown = with_header(1, Compression::None, v1.as_bytes());
+        unknown[HEADER_LEN - 1] = 7;
+        assert!(matches!(
+            decode(&unknown),
+            Err(StorageError::UnknownCompression(7))
+        ));
+    }
+
+    #[test]
+    fn future_versions_are_rejected() {
+        let future = with_header(CURRENT_VERSION + 1, Compression::None, b"(new_field: 1)");
+        assert!(matches!(
+            decode(&future),
+            Err(StorageError::UnsupportedVersion(version)) if version == CURRENT_VERSION + 1
+        ));
+    }
+
+    #[tokio::test]
+    async fn future_saves_are_not_overwritten() {
+        let path = save_path("future");
+        let future = with_header(CURRENT_VERSION + 1, Compression::None, b"(new_field: 1)");
+        std::fs::write(&path, &future).unwrap();
+        let storage = FileStorage::new(&path);
+        assert!(matches!(
+            storage.load().await,
+            Err(StorageError::UnsupportedVersion(_))
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), future);
+    }
+}
@@ -0,0 +1,154 @@
+//! Upgrades save data written by older versions of the bot.
+//!
+//! Whenever [`BotData`] changes in a way older saves can't be parsed into as
+//! they are, [`CURRENT_VERSION`] is bumped by adding a step to [`MIGRATIONS`]
+//! that rewrites data of the previous version into the new shape. Old data is
+//! parsed into a [`Value`] and run through every step after its version.
+
+use ron::{value::Map, Value};
+
+use super::StorageError;
+use crate::BotData;
+
+/// Turns a [`Value`] holding data of one version into data of the next one.
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` migrates version `n` to version `n + 1`.
+const MIGRATIONS: &[Migration] = &[v0_remove_user_id, v1_split_enabled];
+
+pub const CURRENT_VERSION: u16 = MIGRATIONS.len() as u16;
+
+/// Parses `raw` data of the given version, migrating it if it's older than
+/// [`CURRENT_VERSION`]. `version` must not be newer than that.
+pub(crate) fn deserialize(version: u16, raw: &[u8]) -> Result<BotData, StorageError> {
+    if version == CURRENT_VERSION {
+        return Ok(ron::de::from_bytes(raw)?);
+    }
+
+    migrate(version, ron::de::from_bytes(raw)?)
+}
+
+/// Migrates `value` holding data of the given version to [`CURRENT_VERSION`].
+pub(crate) fn migrate(version: u16, mut value: Value) -> Result<BotData, StorageError> {
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version.into()) {
+        migration(&mut value).map_err(|reason| StorageError::Migration {
+            version: from as u16,
+            reason,
+        })?;
+        tracing::info!("migrated data from version {} to {}", from, from + 1);
+    }
+    value.into_rust().map_err(|err| StorageError::Migration {
+        version: CURRENT_VERSION,
+        reason: err.to_string(),
+    })
+}
+
+fn key(name: &str) -> Value {
+    Value::String(name.to_owned())
+}
+
+/// Gets the fields of a struct, which are stored as a map in a [`Value`].
+fn fields<'a>(value: &'a mut Value, name: &str) -> Result<&'a mut Map, String> {
+    match value {
+        Value::Map(map) => Ok(map),
+        _ => Err(format!("`{}` is not a struct", name)),
+    }
+}
+
+/// The bot's user id moved to the adapters, since every platform has its own.
+fn v0_remove_user_id(data: &mut Value) -> Result<(), String> {
+    fields(data, "BotData")?.remove(&key("user_id"));
+    Ok(())
+}
+
+/// `MarkovData::enabled` only decided whether the bot spoke in a channel, every
+/// channel with data learned regardless. It's now split into `learn` and
+/// `speak`.
+fn v1_split_enabled(data: &mut Value) -> Result<(), String> {
+    let mchain = fields(data, "BotData")?
+        .iter_mut()
+        .find(|(name, _)| **name == key("mchain"));
+    let mchain = match mchain {
+        Some((_, mchain)) => fields(mchain, "mchain")?,
+        None => return Ok(()),
+    };
+    for markov in mchain.values_mut() {
+        let markov = fields(markov, "MarkovData")?;
+        let enabled = markov.remove(&key("enabled")).unwrap_or(Value::Bool(false));
+        markov.insert(key("learn"), Value::Bool(true));
+        markov.insert(key("speak"), enabled);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// Saved before the bot ran on several platforms.
+    pub(in crate::storage) const V0: &str = r#"(
+        user_id: 123,
+        insult_data: {"1": (message_id: Some("2"), count_passed: 3, enabled: true)},
+        mchain: {
+            "10": (probability: 0.5, enabled: true, per_user: {}),
+            "11": (probability: 0.0, per_user: {}),
+        },
+        prefix: {"10": "!"},
+    )"#;
+
+    /// Saved before channels could learn without speaking.
+    const V1: &str = r#"(
+        mchain: {
+            "discord:10": (probability: 0.5, enabled: true, guild_id: Some("discord:1")),
+            "discord:11": (probability: 0.0, enabled: false),
+        },
+        prefix: {"discord:10": "!"},
+        users: {"discord:5": (opted_out: true)},
+    )"#;
+
+    #[test]
+    fn v0() {
+        let data = deserialize(0, V0.as_bytes()).unwrap();
+        assert_eq!(*data.prefix.get("10").unwrap(), "!");
+        let insult = data.insult_data.get("1").unwrap();
+        assert_eq!(insult.message_id.as_deref(), Some("2"));
+        assert_eq!((insult.count_passed, insult.enabled), (3, true));
+
+        let speaking = data.mchain.get("10").unwrap();
+        assert_eq!(speaking.probability, 0.5);
+        assert!(speaking.learn && speaking.speak);
+        let silent = data.mchain.get("11").unwrap();
+        assert!(silent.learn && !silent.speak);
+    }
+
+    #[test]
+    fn v1() {
+        let data = deserialize(1, V1.as_bytes()).unwrap();
+        assert_eq!(*data.prefix.get("discord:10").unwrap(), "!");
+        assert!(data.users.get("discord:5").unwrap().opted_out);
+
+        let speaking = data.mchain.get("discord:10").unwrap();
+        assert!(speaking.learn && speaking.speak);
+        assert_eq!(speaking.guild_id.as_deref(), Some("discord:1"));
+        let silent = data.mchain.get("discord:11").unwrap();
+        assert!(silent.learn && !silent.speak);
+    }
+
+    #[test]
+    fn current_version_is_parsed_as_is() {
+        let current = r#"(mchain: {"discord:10": (learn: false, speak: true)})"#;
+        let data = deserialize(CURRENT_VERSION, current.as_bytes()).unwrap();
+        let markov = data.mchain.get("discord:10").unwrap();
+        assert!(!markov.learn && markov.speak);
+    }
+
+    #[test]
+    fn broken_data_is_reported() {
+        let err = deserialize(1, br#"(mchain: {"discord:10": 5})"#).unwrap_err();
+        assert!(
+            matches!(err, StorageError::Migration { version: 1, .. }),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,349 @@
+//! Keeps bot data in an SQLite database.
+//!
+//! Every entry of the maps in [`BotData`] (e.g. the markov data of a channel)
+//! is stored in its own row as lz4 compressed RON, with its kind being the
+//! name of the field it belongs to. Saves only write the rows of entries that
+//! changed.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::Path,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use ron::{value::Map, Value};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use smol_str::SmolStr;
+
+use super::{compress, decompress, migrations, Changes, Storage, StorageError, CURRENT_VERSION};
+use crate::BotData;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS entries (
+    kind TEXT NOT NULL,
+    id TEXT NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (kind, id)
+);";
+
+const MCHAIN: &str = "mchain";
+const INSULT_DATA: &str = "insult_data";
+const PREFIX: &str = "prefix";
+const USERS: &str = "users";
+const GUILDS: &str = "guilds";
+const PERMS: &str = "perms";
+
+type Row = (String, SmolStr, Vec<u8>);
+
+#[derive(Debug)]
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs `f` with the connection on the blocking thread pool.
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StorageError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&mut conn.lock()))
+            .await
+            .map_err(|err| StorageError::Io(io::Error::other(err)))?
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn load(&self) -> Result<Option<BotData>, StorageError> {
+        let (version, rows) = self
+            .with_conn(|conn| {
+                let version: Option<u16> = conn
+                    .query_row("SELECT value FROM meta WHERE key = 'version'", [], |row| {
+                        row.get(0)
+                    })
+                    .optional()?;
+                let mut stmt = conn.prepare("SELECT kind, id, data FROM entries")?;
+                let rows = stmt
+                    .query_map([], |row| {
+                        Ok((row.get(0)?, row.get::<_, String>(1)?.into(), row.get(2)?))
+                    })?
+                    .collect::<Result<Vec<Row>, _>>()?;
+                Ok((version, rows))
+            })
+            .await?;
+
+        let version = match version {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+        if version > CURRENT_VERSION {
+            return Err(StorageError::UnsupportedVersion(version));
+        }
+        if version < CURRENT_VERSION {
+            let data = migrate(version, rows)?;
+            // every row has to be rewritten in the new format
+            data.mark_all_changed();
+            return Ok(Some(data));
+        }
+
+        let data = BotData::default();
+        for (kind, id, bytes) in rows {
+            match kind.as_str() {
+                MCHAIN => {
+                    data.mchain.insert(id, decompress(&bytes)?);
+                }
+                INSULT_DATA => {
+                    data.insult_data.insert(id, decompress(&bytes)?);
+                }
+                PREFIX => {
+                    data.prefix.insert(id, decompress(&bytes)?);
+                }
+                USERS => {
+                    data.users.insert(id, decompress(&bytes)?);
+                }
+                GUILDS => {
+                    data.guilds.insert(id, decompress(&bytes)?);
+                }
+                PERMS => {
+                    data.perms.insert(id, decompress(&bytes)?);
+                }
+                kind => tracing::warn!("ignoring unknown entry `{}` of kind `{}`", id, kind),
+            }
+        }
+        Ok(Some(data))
+    }
+
+    async fn save(&self, data: &BotData, changes: &Changes) -> Result<(), StorageError> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let mut writes = Vec::new();
+        collect_writes(&mut writes, MCHAIN, &data.mchain, &changes.mchain)?;
+        collect_writes(
+            &mut writes,
+            INSULT_DATA,
+            &data.insult_data,
+            &changes.insult_data,
+        )?;
+        collect_writes(&mut writes, PREFIX, &data.prefix, &changes.prefix)?;
+        collect_writes(&mut writes, USERS, &data.users, &changes.users)?;
+        collect_writes(&mut writes, GUILDS, &data.guilds, &changes.guilds)?;
+        collect_writes(&mut writes, PERMS, &data.perms, &changes.perms)?;
+
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut upsert = tx.prepare(
+                    "INSERT INTO entries (kind, id, data) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (kind, id) DO UPDATE SET data = excluded.data",
+                )?;
+                let mut delete = tx.prepare("DELETE FROM entries WHERE kind = ?1 AND id = ?2")?;
+                for (kind, id, bytes) in &writes {
+                    match bytes {
+                        Some(bytes) => upsert.execute(params![kind, id.as_str(), bytes])?,
+                        None => delete.execute(params![kind, id.as_str()])?,
+                    };
+                }
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES ('version', ?1)",
+                [CURRENT_VERSION],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Compresses the changed entries of `map`, or `None` for the ones that were removed.
+fn collect_writes<V: Serialize>(
+    writes: &mut Vec<(&'static str, SmolStr, Option<Vec<u8>>)>,
+    kind: &'static str,
+    map: &DashMap<SmolStr, V>,
+    changed: &HashSet<SmolStr>,
+) -> Result<(), StorageError> {
+    for id in changed {
+        let bytes = map
+            .get(id)
+            .map(|entry| compress(entry.value()))
+            .transpose()?;
+        writes.push((kind, id.clone(), bytes));
+    }
+    Ok(())
+}
+
+/// Puts the rows back together into the shape of a whole save, so they go
+/// through the same migrations.
+fn migrate(version: u16, rows: Vec<Row>) -> Result<BotData, StorageError> {
+    let mut fields: HashMap<String, Map> = HashMap::new();
+    for (kind, id, bytes) in rows {
+        let value: Value = decompress(&bytes)?;
+        fields
+            .entry(kind)
+            .or_default()
+            .insert(Value::String(id.into()), value);
+    }
+    let data = fields
+        .into_iter()
+        .map(|(kind, entries)| (Value::String(kind), Value::Map(entries)))
+        .collect();
+    migrations::migrate(version, Value::Map(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(prefix: &[&str]) -> Changes {
+        Changes {
+            prefix: prefix.iter().map(|&id| id.into()).collect(),
+            ..Changes::default()
+        }
+    }
+
+    fn prefixes(data: &BotData) -> Vec<(String, String)> {
+        let mut prefixes = data
+            .prefix
+            .iter()
+            .map(|entry| (entry.key().to_string(), entry.value().to_string()))
+            .collect::<Vec<_>>();
+        prefixes.sort();
+        prefixes
+    }
+
+    fn row_count(storage: &SqliteStorage) -> usize {
+        storage
+            .conn
+            .lock()
+            .query_row("SELECT COUNT(*) FROM entries", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        assert!(storage.load().await.unwrap().is_none());
+
+        let data = BotData::default();
+        data.prefix.insert("discord:1".into(), "!".into());
+        data.users.insert("discord:5".into(), Default::default());
+        data.markov_entry("discord:10").speak = true;
+        let mut changed = changes(&["discord:1"]);
+        changed.users.insert("discord:5".into());
+        changed.mchain.insert("discord:10".into());
+        storage.save(&data, &changed).await.unwrap();
+
+        let loaded = storage.load().await.unwrap().unwrap();
+        assert_eq!(prefixes(&loaded), [("discord:1".into(), "!".into())]);
+        assert!(loaded.users.contains_key("discord:5"));
+        assert!(loaded.mchain.get("discord:10").unwrap().speak);
+        assert_eq!(row_count(&storage), 3);
+    }
+
+    #[tokio::test]
+    async fn only_writes_changes() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        let data = BotData::default();
+        data.prefix.insert("discord:1".into(), "!".into());
+        data.prefix.insert("discord:2".into(), "?".into());
+        storage
+            .save(&data, &changes(&["discord:1", "discord:2"]))
+            .await
+            .unwrap();
+
+        data.prefix.insert("discord:1".into(), "a".into());
+        data.prefix.insert("discord:2".into(), "b".into());
+        storage.save(&data, &changes(&["discord:2"])).await.unwrap();
+        // nothing changed, so nothing is written
+        data.prefix.insert("discord:2".into(), "c".into());
+        storage.save(&data, &changes(&[])).await.unwrap();
+
+        let loaded = storage.load().await.unwrap().unwrap();
+        assert_eq!(
+            prefixes(&loaded),
+            [
+                ("discord:1".into(), "!".into()),
+                ("discord:2".into(), "b".into())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn deletes_removed_entries() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        let data = BotData::default();
+        data.prefix.insert("discord:1".into(), "!".into());
+        data.prefix.insert("discord:2".into(), "?".into());
+        storage
+            .save(&data, &changes(&["discord:1", "discord:2"]))
+            .await
+            .unwrap();
+
+        data.prefix.remove("discord:1");
+        storage.save(&data, &changes(&["discord:1"])).await.unwrap();
+
+        let loaded = storage.load().await.unwrap().unwrap();
+        assert_eq!(prefixes(&loaded), [("discord:2".into(), "?".into())]);
+        assert_eq!(row_count(&storage), 1);
+    }
+
+    #[tokio::test]
+    async fn migrates_old_rows() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        {
+            let conn = storage.conn.lock();
+            conn.execute("INSERT INTO meta (key, value) VALUES ('version', 1)", [])
+                .unwrap();
+            let markov: Value = ron::from_str("(probability: 0.5, enabled: true)").unwrap();
+            conn.execute(
+                "INSERT INTO entries (kind, id, data) VALUES (?1, ?2, ?3)",
+                params![MCHAIN, "discord:10", compress(&markov).unwrap()],
+            )
+            .unwrap();
+        }
+
+        let loaded = storage.load().await.unwrap().unwrap();
+        let markov = loaded.mchain.get("discord:10").unwrap();
+        assert!(markov.learn && markov.speak);
+        // every row is rewritten in the new format on the next save
+        assert!(loaded.changes.lock().mchain.contains("discord:10"));
+    }
+
+    #[tokio::test]
+    async fn future_versions_are_rejected() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage
+            .conn
+            .lock()
+            .execute(
+                "INSERT INTO meta (key, value) VALUES ('version', ?1)",
+                [CURRENT_VERSION + 1],
+            )
+            .unwrap();
+        assert!(matches!(
+            storage.load().await,
+            Err(StorageError::UnsupportedVersion(_))
+        ));
+    }
+}
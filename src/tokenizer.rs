@@ -0,0 +1,262 @@
+//! Splitting messages into the tokens markov chains are fed, and joining
+//! generated tokens back into text.
+//!
+//! Punctuation is split off from words, so "hello," and "hello" end up as the
+//! same state. Links, mentions and emoji are kept as single tokens, and code
+//! is dropped entirely since chopping it up only produces garbage.
+
+use std::{borrow::Cow, ops::Deref};
+
+use smol_str::SmolStr;
+
+/// Punctuation that sticks to the token before it.
+const CLOSING: &[char] = &['.', ',', '!', '?', ';', ':', ')', ']', '}', '%', '…'];
+/// Punctuation that sticks to the token after it.
+const OPENING: &[char] = &['(', '[', '{'];
+/// Punctuation that is kept together when repeated, like "..." or "?!".
+const SENTENCE_END: &[char] = &['.', '!', '?', '…'];
+/// Apostrophes and hyphens that are part of a word when between letters.
+const WORD_JOINERS: &[char] = &['\'', '’', '-'];
+
+pub fn tokenize(text: &str) -> Vec<SmolStr> {
+    let mut tokens = Vec::new();
+    for word in strip_code(text).split_whitespace() {
+        tokenize_word(word, &mut tokens);
+    }
+    tokens
+}
+
+/// Joins `tokens` with spaces, except around punctuation where it would look off.
+pub fn detokenize<S: Deref<Target = str>>(tokens: impl IntoIterator<Item = S>) -> String {
+    let mut text = String::new();
+    let mut space_next = false;
+    let mut in_quote = false;
+    for token in tokens {
+        let token = &*token;
+        let (space_before, space_after) = if token == "\"" {
+            in_quote = !in_quote;
+            (in_quote, !in_quote)
+        } else if is_made_of(token, CLOSING) {
+            (false, true)
+        } else if is_made_of(token, OPENING) {
+            (true, false)
+        } else {
+            (true, true)
+        };
+        if space_next && space_before {
+            text.push(' ');
+        }
+        text.push_str(token);
+        space_next = space_after;
+    }
+    text
+}
+
+fn is_made_of(token: &str, chars: &[char]) -> bool {
+    !token.is_empty() && token.chars().all(|c| chars.contains(&c))
+}
+
+/// Replaces inline code and code blocks with a space.
+fn strip_code(text: &str) -> Cow<'_, str> {
+    if !text.contains('`') {
+        return text.into();
+    }
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('`') {
+        stripped.push_str(&rest[..start]);
+        let fence = if rest[start..].starts_with("```") {
+            "```"
+        } else {
+            "`"
+        };
+        let code = &rest[start + fence.len()..];
+        match code.find(fence) {
+            Some(end) => {
+                stripped.push(' ');
+                rest = &code[end + fence.len()..];
+            }
+            // not actually code
+            None => {
+                stripped.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    stripped.push_str(rest);
+    stripped.into()
+}
+
+fn tokenize_word(mut word: &str, tokens: &mut Vec<SmolStr>) {
+    while !word.is_empty() {
+        let len = atomic_len(word).unwrap_or_else(|| plain_len(word));
+        tokens.push(word[..len].into());
+        word = &word[len..];
+    }
+}
+
+/// Length of the link, mention or emoji at the start of `word`, if there is one.
+fn atomic_len(word: &str) -> Option<usize> {
+    if word.starts_with("http://") || word.starts_with("https://") || word.starts_with('@') {
+        // trailing punctuation is most likely not part of it
+        let len = word.trim_end_matches(CLOSING).trim_end_matches('"').len();
+        return (len > 1).then_some(len);
+    }
+    if let Some(inner) = word.strip_prefix('<') {
+        // discord mentions, channels, custom emoji and timestamps: <@id>, <#id>, <:name:id>, <t:123>
+        let end = inner.find('>')?;
+        let inner = &inner[..end];
+        let is_special = ["@", "#", ":", "a:", "t:"]
+            .iter()
+            .any(|prefix| inner.starts_with(prefix));
+        return is_special.then_some(end + 2);
+    }
+    if let Some(inner) = word.strip_prefix(':') {
+        // :shortcode: emoji
+        let end = inner.find(':')?;
+        let name = &inner[..end];
+        let is_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '_' | '+' | '-'));
+        return is_name.then_some(end + 2);
+    }
+    None
+}
+
+/// Length of the word, punctuation or emoji at the start of `word`.
+fn plain_len(word: &str) -> usize {
+    let first = word.chars().next().expect("word isn't empty");
+    let mut len = first.len_utf8();
+    // keycaps, like 1️⃣, start with a plain digit, # or *
+    if matches!(first, '0'..='9' | '#' | '*') {
+        let rest = &word[len..];
+        let keycap = rest.strip_prefix('\u{FE0F}').unwrap_or(rest);
+        if keycap.starts_with('\u{20E3}') {
+            return word.len() - keycap.len() + '\u{20E3}'.len_utf8();
+        }
+    }
+    let mut prev = first;
+    let mut chars = word[len..].chars().peekable();
+
+    if first.is_alphanumeric() {
+        while let Some(c) = chars.next() {
+            let next = chars.peek().copied();
+            let joins_word = WORD_JOINERS.contains(&c) && next.is_some_and(char::is_alphanumeric);
+            let joins_number = matches!(c, '.' | ',')
+                && prev.is_ascii_digit()
+                && next.is_some_and(|c| c.is_ascii_digit());
+            if !(c.is_alphanumeric() || joins_word || joins_number) {
+                break;
+            }
+            len += c.len_utf8();
+            prev = c;
+        }
+    } else if SENTENCE_END.contains(&first) {
+        len += chars
+            .take_while(|c| SENTENCE_END.contains(c))
+            .map(char::len_utf8)
+            .sum::<usize>();
+    } else if !first.is_ascii_punctuation() {
+        // emoji, keeping modifiers and zero width joined sequences together
+        for c in chars {
+            let is_modifier = matches!(
+                c,
+                '\u{FE0E}' | '\u{FE0F}' | '\u{20E3}' | '\u{1F3FB}'..='\u{1F3FF}'
+            );
+            if !(is_modifier || c == '\u{200D}' || prev == '\u{200D}') {
+                break;
+            }
+            len += c.len_utf8();
+            prev = c;
+        }
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let cases: &[(&str, &[&str])] = &[
+            ("hello world", &["hello", "world"]),
+            ("hello, world!", &["hello", ",", "world", "!"]),
+            ("wait... what?!", &["wait", "...", "what", "?!"]),
+            ("(really) [yes]", &["(", "really", ")", "[", "yes", "]"]),
+            ("\"quoted\"", &["\"", "quoted", "\""]),
+            ("don't well-known", &["don't", "well-known"]),
+            ("'tis -ish", &["'", "tis", "-", "ish"]),
+            (
+                "pi is 3.14, or 1,000.",
+                &["pi", "is", "3.14", ",", "or", "1,000", "."],
+            ),
+            ("100%", &["100", "%"]),
+            // links
+            (
+                "see https://example.com/a?b=c.",
+                &["see", "https://example.com/a?b=c", "."],
+            ),
+            ("(http://example.com)", &["(", "http://example.com", ")"]),
+            // mentions
+            ("hi @everyone!", &["hi", "@everyone", "!"]),
+            (
+                "<@123>, <@!45> and <#67>",
+                &["<@123>", ",", "<@!45>", "and", "<#67>"],
+            ),
+            ("<t:1700000000:R>", &["<t:1700000000:R>"]),
+            ("<b>", &["<", "b", ">"]),
+            // emoji
+            ("nice<:cat:89>:smile:", &["nice", "<:cat:89>", ":smile:"]),
+            ("<a:dance:12>!", &["<a:dance:12>", "!"]),
+            ("yes👍🏽👍", &["yes", "👍🏽", "👍"]),
+            ("👨‍👩‍👧 family", &["👨‍👩‍👧", "family"]),
+            ("keycap 1️⃣", &["keycap", "1️⃣"]),
+            ("#️⃣*\u{20E3}", &["#️⃣", "*\u{20E3}"]),
+            ("a :not an emoji:", &["a", ":", "not", "an", "emoji", ":"]),
+            // code
+            ("look `let x = 1;` here", &["look", "here"]),
+            ("a```\nfn main() {}\n```b", &["a", "b"]),
+            ("``` unclosed", &["`", "`", "`", "unclosed"]),
+            ("", &[]),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(tokenize(text), *expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn joining() {
+        let cases: &[(&[&str], &str)] = &[
+            (&["hello", ",", "world", "!"], "hello, world!"),
+            (&["wait", "...", "what", "?!"], "wait... what?!"),
+            (&["(", "really", ")", "yes"], "(really) yes"),
+            (&["he", "said", "\"", "hi", "\"", "ok"], "he said \"hi\" ok"),
+            (&["\"", "hi", "\"", "."], "\"hi\"."),
+            (&["50", "%", "off"], "50% off"),
+            (&[",", "leading"], ", leading"),
+            (&[], ""),
+        ];
+        for (tokens, expected) in cases {
+            assert_eq!(
+                detokenize(tokens.iter().copied()),
+                *expected,
+                "{:?}",
+                tokens
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for text in [
+            "hello, world!",
+            "wait... what?! (really)",
+            "she said \"no\" and left.",
+            "see https://example.com, <@123> :smile:",
+        ] {
+            assert_eq!(detokenize(tokenize(text)), text);
+        }
+    }
+}
@@ -0,0 +1,962 @@
+//! Runs every command through [`Bot::process_args`] with a [`MockHandler`].
+
+mod common;
+
+use bernbot::{
+    config::Config, perms::Level, ratelimit::RateLimit, Bot, BotError, CHANNEL_MARK_MSG,
+    NOT_ENOUGH_PERMS,
+};
+use common::{admin_command, bot, bot_with, command, listen_to, run, tmp_dir, MockHandler};
+
+#[tokio::test]
+async fn help() {
+    let bot = bot();
+    let help = command(&bot, "b/help").await;
+    assert!(help.starts_with("commands are:"));
+    for name in [
+        "help", "set", "poem", "gen", "listen", "privacy", "perms", "fuckyou",
+    ] {
+        assert!(
+            help.contains(&format!("- `{}`: ", name)),
+            "{} missing",
+            name
+        );
+    }
+
+    let listen = command(&bot, "b/help listen").await;
+    assert!(listen.starts_with("markov chain listener management commands"));
+    assert!(listen.contains("usage: `listen` (needs moderator)"));
+    assert!(listen.contains("- `prob [value]`: "));
+    assert!(listen.contains("  - `setting`: one of"));
+
+    assert_eq!(
+        command(&bot, "b/help generate").await,
+        command(&bot, "b/help gen").await
+    );
+    assert!(command(&bot, "b/help gen")
+        .await
+        .contains("also called `generate`"));
+    assert!(command(&bot, "b/help nope")
+        .await
+        .ends_with("`nope` isn't a command."));
+}
+
+#[tokio::test]
+async fn aliases() {
+    let bot = bot();
+    let umad = run(&bot, MockHandler::new("b/umad")).await.sent();
+    assert_eq!(umad[0].attachment.as_deref(), Some("umad.jpg"));
+    assert_eq!(
+        command(&bot, "b/generate").await,
+        command(&bot, "b/gen").await
+    );
+}
+
+#[tokio::test]
+async fn empty_command() {
+    let bot = bot();
+    assert_eq!(command(&bot, "b/").await, "What do you want?");
+}
+
+#[tokio::test]
+async fn messages_without_prefix_are_ignored() {
+    let bot = bot();
+    assert!(run(&bot, MockHandler::new("help")).await.sent().is_empty());
+}
+
+#[tokio::test]
+async fn set_prefix() {
+    let bot = bot();
+    assert_eq!(command(&bot, "b/set prefix !").await, NOT_ENOUGH_PERMS);
+    assert_eq!(
+        admin_command(&bot, "b/set prefix !").await,
+        "prefix is now `!`."
+    );
+    assert!(command(&bot, "!help").await.starts_with("commands are:"));
+    assert!(run(&bot, MockHandler::new("b/help"))
+        .await
+        .sent()
+        .is_empty());
+    assert_eq!(admin_command(&bot, "!set prefix").await, "no value");
+}
+
+#[tokio::test]
+async fn prefix_is_per_guild() {
+    let bot = bot();
+    admin_command(&bot, "b/set prefix !").await;
+    let other_channel = run(&bot, MockHandler::new("!help").channel("other")).await;
+    assert!(other_channel.reply().starts_with("commands are:"));
+    let other_guild = run(&bot, MockHandler::new("b/help").guild(Some("other"))).await;
+    assert!(other_guild.reply().starts_with("commands are:"));
+}
+
+#[tokio::test]
+async fn set_insult() {
+    let bot = bot();
+    assert_eq!(command(&bot, "b/set insult").await, NOT_ENOUGH_PERMS);
+    assert_eq!(
+        admin_command(&bot, "b/set insult").await,
+        "turned on insults"
+    );
+    assert_eq!(
+        admin_command(&bot, "b/set insult").await,
+        "turned off insults"
+    );
+}
+
+#[tokio::test]
+async fn set_without_setting() {
+    let bot = bot();
+    assert!(admin_command(&bot, "b/set")
+        .await
+        .ends_with("`set` isn't a command."));
+    assert!(admin_command(&bot, "b/set nope")
+        .await
+        .ends_with("`nope` isn't a command."));
+}
+
+#[tokio::test]
+async fn poem() {
+    let bot = bot();
+    assert!(!command(&bot, "b/poem").await.is_empty());
+    assert!(command(&bot, "b/poem princess")
+        .await
+        .to_lowercase()
+        .contains("princess"));
+    assert_eq!(
+        command(&bot, "b/poem zzzzzzzzzzzz").await,
+        "No poem with those words. Try again, maybe a miracle will occur."
+    );
+    // stemmed, so other forms of the words are found
+    assert!(command(&bot, "b/poem girls crying")
+        .await
+        .trim_start()
+        .starts_with("She dropped her beads in the sand."));
+
+    let fifth = command(&bot, "b/poem #5").await;
+    assert!(fifth
+        .trim_start()
+        .starts_with("She dropped her beads in the sand."));
+    assert_eq!(
+        command(&bot, "b/poem #0").await,
+        "I looked everywhere, there is no poem #0, there are only 33 of them. Stop making things up."
+    );
+}
+
+#[tokio::test]
+async fn poem_search() {
+    let bot = bot();
+    assert_eq!(
+        command(&bot, "b/poem search sand").await,
+        "poems matching `sand`, page 1 of 1:
+- `#5` She dropped her beads in the sand. So the girl cried.
+
+use `poem #<n>` to read one"
+    );
+
+    let princess = command(&bot, "b/poem search princess").await;
+    let listed = princess
+        .lines()
+        .filter(|line| line.starts_with("- `#"))
+        .count();
+    assert_eq!(listed, 3);
+    // with a line matching the search if the first one doesn't
+    assert!(command(&bot, "b/poem search ocean").await.contains(
+        "- `#5` She dropped her beads in the sand. So the girl cried.
+  > They might be in the ocean, not the sand. So the girl cried."
+    ));
+
+    let first = command(&bot, "b/poem search the").await;
+    assert!(first.starts_with("poems matching `the`, page 1 of "));
+    assert!(first.ends_with("and `poem search the 2` for more"));
+    let second = command(&bot, "b/poem search the 2").await;
+    assert!(second.starts_with("poems matching `the`, page 2 of "));
+    assert_ne!(first, second);
+    assert!(command(&bot, "b/poem search the 100")
+        .await
+        .starts_with("I looked everywhere, there is no page 100 of the results"));
+
+    // phrases only match where the words are together
+    assert!(command(&bot, "b/poem search \"the girl cried\"")
+        .await
+        .contains("`#5`"));
+    assert_eq!(
+        command(&bot, "b/poem search \"cried the girl\"").await,
+        "No poem with those words. Try again, maybe a miracle will occur."
+    );
+}
+
+#[tokio::test]
+async fn fuckyou() {
+    let bot = bot();
+    let handler = run(&bot, MockHandler::new("b/fuckyou")).await;
+    let sent = handler.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].attachment.as_deref(), Some("umad.jpg"));
+    assert!(sent[0].reply);
+}
+
+#[tokio::test]
+async fn gen_needs_a_listened_channel() {
+    let bot = bot();
+    assert_eq!(command(&bot, "b/gen").await, CHANNEL_MARK_MSG);
+    assert_eq!(command(&bot, "b/gen user").await, CHANNEL_MARK_MSG);
+}
+
+#[tokio::test]
+async fn gen() {
+    let bot = bot();
+    listen_to(&bot, &["hello there"]).await;
+    assert_eq!(command(&bot, "b/gen").await, "hello there");
+    assert_eq!(command(&bot, "b/gen token there").await, "there");
+    assert_eq!(command(&bot, "b/gen token").await, "put a token");
+    assert_eq!(command(&bot, "b/gen user").await, "hello there");
+    assert_eq!(
+        command(&bot, "b/gen someone").await,
+        "User has no messages recorded"
+    );
+    assert!(!command(&bot, "b/gen poem").await.is_empty());
+}
+
+#[tokio::test]
+async fn gen_poem_without_capitals() {
+    let dir = common::unique_tmp_path("lowercase_poems");
+    std::fs::create_dir_all(&dir).unwrap();
+    let poems = "no capitals here.-none at all!";
+    std::fs::write(dir.join("poems.txt"), poems).unwrap();
+    let bot = bot_with(Config {
+        resources_dir: Some(dir),
+        cooldowns: Default::default(),
+        ..Config::default()
+    });
+    let poem = command(&bot, "b/gen poem").await;
+    let words = poems.split(['-', ' ']).collect::<Vec<_>>();
+    assert!(!poem.is_empty());
+    assert!(
+        poem.split_whitespace().all(|word| words.contains(&word)),
+        "{}",
+        poem
+    );
+}
+
+#[tokio::test]
+async fn gen_seed_is_reproducible() {
+    let bot = bot();
+    listen_to(
+        &bot,
+        &[
+            "the cat sat on the mat",
+            "the dog sat on the cat",
+            "a cat and a dog",
+        ],
+    )
+    .await;
+    let first = command(&bot, "b/gen seed 42").await;
+    for _ in 0..5 {
+        assert_eq!(command(&bot, "b/gen seed 42").await, first);
+    }
+    assert_eq!(
+        command(&bot, "b/gen seed 7 poem").await,
+        command(&bot, "b/gen seed 7 poem").await
+    );
+    assert_eq!(
+        command(&bot, "b/gen seed x").await,
+        "the seed has to be a number. Try again, dumb human."
+    );
+}
+
+#[tokio::test]
+async fn listen_toggle() {
+    let bot = bot();
+    assert_eq!(command(&bot, "b/listen").await, NOT_ENOUGH_PERMS);
+    assert_eq!(admin_command(&bot, "b/listen").await, "marked channel");
+    assert_eq!(admin_command(&bot, "b/listen").await, "unmarked channel");
+}
+
+// speaking keeps going for a while with a delay between messages
+#[tokio::test(start_paused = true)]
+async fn listen_learn_and_speak() {
+    let bot = bot();
+    assert_eq!(
+        admin_command(&bot, "b/listen learn").await,
+        "learning from this channel"
+    );
+    run(&bot, MockHandler::new("hello there")).await;
+    assert_eq!(command(&bot, "b/gen").await, "hello there");
+    assert_eq!(
+        admin_command(&bot, "b/listen learn").await,
+        "stopped learning from this channel"
+    );
+    run(&bot, MockHandler::new("something else")).await;
+    assert_eq!(command(&bot, "b/gen").await, "hello there");
+
+    admin_command(&bot, "b/listen prob 100").await;
+    assert!(run(&bot, MockHandler::new("hello")).await.sent().is_empty());
+    assert_eq!(
+        admin_command(&bot, "b/listen speak").await,
+        "speaking in this channel"
+    );
+    assert!(!run(&bot, MockHandler::new("hello")).await.sent().is_empty());
+}
+
+#[tokio::test]
+async fn listen_prob() {
+    let bot = bot();
+    assert_eq!(command(&bot, "b/listen prob").await, CHANNEL_MARK_MSG);
+    admin_command(&bot, "b/listen").await;
+    assert_eq!(command(&bot, "b/listen prob").await, "Probability is 5%");
+    assert_eq!(command(&bot, "b/listen prob 50").await, NOT_ENOUGH_PERMS);
+    assert_eq!(
+        admin_command(&bot, "b/listen prob 50").await,
+        "Set probability to 50%"
+    );
+    assert_eq!(command(&bot, "b/listen prob").await, "Probability is 50%");
+    assert_eq!(
+        admin_command(&bot, "b/listen prob 500").await,
+        "Set probability to 100%"
+    );
+    assert_eq!(
+        admin_command(&bot, "b/listen prob 0.5").await,
+        "Set probability to 0.5%"
+    );
+    assert_eq!(command(&bot, "b/listen prob").await, "Probability is 0.5%");
+    assert_eq!(
+        admin_command(&bot, "b/listen prob 12.5").await,
+        "Set probability to 12.5%"
+    );
+    assert_eq!(
+        admin_command(&bot, "b/listen prob -1").await,
+        "Set probability to 0%"
+    );
+    assert_eq!(
+        admin_command(&bot, "b/listen prob NaN").await,
+        "Set probability to 5%"
+    );
+}
+
+#[tokio::test]
+async fn listen_order() {
+    let bot = bot();
+    admin_command(&bot, "b/listen").await;
+    assert_eq!(command(&bot, "b/listen order 2").await, NOT_ENOUGH_PERMS);
+    assert!(admin_command(&bot, "b/listen order 2").await.contains('2'));
+    assert!(command(&bot, "b/listen order").await.contains('2'));
+}
+
+#[tokio::test]
+async fn listen_filter() {
+    let bot = bot();
+    listen_to(&bot, &[]).await;
+    assert_eq!(
+        command(&bot, "b/listen filter min 5").await,
+        NOT_ENOUGH_PERMS
+    );
+    admin_command(&bot, "b/listen filter min 5").await;
+    assert!(command(&bot, "b/listen filter")
+        .await
+        .contains("min length: 5"));
+    run(&bot, MockHandler::new("hi")).await;
+    run(&bot, MockHandler::new("hello there")).await;
+    assert_eq!(command(&bot, "b/gen").await, "hello there");
+}
+
+#[tokio::test]
+async fn ignored_users() {
+    let bot = bot();
+    listen_to(&bot, &[]).await;
+    assert!(admin_command(&bot, "b/listen filter ignore <@!spammer>")
+        .await
+        .contains("ignored users: spammer"));
+    run(&bot, MockHandler::new("buy now").author("spammer")).await;
+    run(&bot, MockHandler::new("hello there")).await;
+    assert_eq!(command(&bot, "b/gen").await, "hello there");
+
+    assert!(admin_command(&bot, "b/listen filter unignore <@spammer>")
+        .await
+        .contains("ignored users: none"));
+}
+
+#[tokio::test]
+async fn bots_are_ignored() {
+    let bot = bot();
+    listen_to(&bot, &[]).await;
+    run(&bot, MockHandler::new("beep boop").author_is_bot(true)).await;
+    run(&bot, MockHandler::new("own message").author(common::BOT_ID)).await;
+    assert_eq!(command(&bot, "b/gen").await, "");
+}
+
+#[tokio::test]
+async fn listen_clear() {
+    let bot = bot();
+    listen_to(&bot, &["hello there"]).await;
+    assert_eq!(command(&bot, "b/listen clear").await, NOT_ENOUGH_PERMS);
+    assert!(admin_command(&bot, "b/listen clear")
+        .await
+        .contains("add `confirm`"));
+    assert_eq!(command(&bot, "b/gen").await, "hello there");
+    assert_eq!(
+        admin_command(&bot, "b/listen clear confirm").await,
+        "cleared data"
+    );
+    assert_eq!(command(&bot, "b/gen").await, "");
+    // settings are kept
+    assert_eq!(command(&bot, "b/listen prob").await, "Probability is 0%");
+}
+
+#[tokio::test]
+async fn listen_clear_only_clears_the_channel() {
+    let bot = bot();
+    listen_to(&bot, &["hello there"]).await;
+    let other = |content| MockHandler::new(content).channel("other");
+    run(&bot, other("b/listen").level(Level::Moderator)).await;
+    run(&bot, other("general kenobi")).await;
+    admin_command(&bot, "b/listen clear confirm").await;
+    assert_eq!(run(&bot, other("b/gen")).await.reply(), "general kenobi");
+
+    assert_eq!(
+        admin_command(&bot, "b/listen clear all confirm").await,
+        "cleared data of 2 channels"
+    );
+    assert_eq!(run(&bot, other("b/gen")).await.reply(), "");
+}
+
+#[tokio::test]
+async fn listen_clear_user() {
+    let bot = bot();
+    listen_to(&bot, &[]).await;
+    run(&bot, MockHandler::new("hello there").author("someone")).await;
+    assert_eq!(
+        admin_command(&bot, "b/listen clear user <@someone> confirm").await,
+        "forgot `someone` in this channel, but it's still mixed into the chains of 1 of them since they learned it before a corpus was kept. clear them to get rid of it."
+    );
+    assert_eq!(
+        command(&bot, "b/gen someone").await,
+        "User has no messages recorded"
+    );
+    assert!(admin_command(&bot, "b/listen clear nope")
+        .await
+        .starts_with("expected"));
+}
+
+#[tokio::test]
+async fn listen_server() {
+    let bot = bot();
+    listen_to(&bot, &["before the server chain"]).await;
+    let dm = MockHandler::new("b/listen server")
+        .level(Level::Admin)
+        .guild(None);
+    assert!(run(&bot, dm).await.reply().contains("no server here"));
+    assert_eq!(command(&bot, "b/listen server").await, NOT_ENOUGH_PERMS);
+    assert_eq!(
+        command(&bot, "b/gen server").await,
+        "this server has no chain, turn it on with `listen server`"
+    );
+
+    assert_eq!(
+        admin_command(&bot, "b/listen server").await,
+        "learning for the whole server from now on"
+    );
+    run(&bot, MockHandler::new("hello there")).await;
+    assert_eq!(command(&bot, "b/gen server").await, "hello there");
+    // other servers have their own
+    let elsewhere = MockHandler::new("b/gen server").guild(Some("other"));
+    assert_eq!(
+        run(&bot, elsewhere).await.reply(),
+        "this server has no chain, turn it on with `listen server`"
+    );
+
+    assert_eq!(
+        admin_command(&bot, "b/listen server").await,
+        "stopped learning for the whole server"
+    );
+    assert_eq!(
+        command(&bot, "b/gen server").await,
+        "this server has no chain, turn it on with `listen server`"
+    );
+}
+
+#[tokio::test]
+async fn gen_from() {
+    let bot = bot();
+    listen_to(&bot, &["hello there"]).await;
+    let other = |content| MockHandler::new(content).channel("other");
+    run(&bot, other("b/listen").level(Level::Moderator)).await;
+    run(&bot, other("b/listen prob 0").level(Level::Moderator)).await;
+    run(&bot, other("general kenobi")).await;
+
+    assert_eq!(command(&bot, "b/gen from other").await, "general kenobi");
+    assert_eq!(command(&bot, "b/gen from <#other>").await, "general kenobi");
+    assert_eq!(command(&bot, "b/gen from").await, "put a channel");
+    for content in ["b/gen from nowhere", "b/gen from <#nowhere>"] {
+        assert_eq!(
+            command(&bot, content).await,
+            "that channel isn't listened to in this server"
+        );
+    }
+    let elsewhere = MockHandler::new("b/gen from other")
+        .channel("elsewhere")
+        .guild(Some("elsewhere"));
+    assert_eq!(
+        run(&bot, elsewhere).await.reply(),
+        "that channel isn't listened to in this server"
+    );
+    let dm = MockHandler::new("b/gen from other").guild(None);
+    assert_eq!(
+        run(&bot, dm).await.reply(),
+        "that channel isn't listened to in this server"
+    );
+}
+
+#[tokio::test]
+async fn server_chain_is_rebuilt_from_the_corpus() {
+    let bot = bot_with(Config {
+        corpus_dir: Some(common::unique_tmp_path("corpus")),
+        user_rate_limit: None,
+        channel_rate_limit: None,
+        guild_rate_limit: None,
+        cooldowns: Default::default(),
+        ..Config::default()
+    });
+    // logged, but filtered out
+    listen_to(&bot, &[]).await;
+    run(&bot, MockHandler::new("beep boop").author_is_bot(true)).await;
+    run(&bot, MockHandler::new("hello there")).await;
+    // logged, but the channel doesn't learn anymore
+    let other = |content| MockHandler::new(content).channel("other");
+    run(&bot, other("b/listen").level(Level::Moderator)).await;
+    run(&bot, other("b/listen prob 0").level(Level::Moderator)).await;
+    run(&bot, other("general kenobi")).await;
+    assert_eq!(
+        run(&bot, other("b/listen learn").level(Level::Moderator))
+            .await
+            .reply(),
+        "stopped learning from this channel"
+    );
+
+    assert_eq!(
+        admin_command(&bot, "b/listen server").await,
+        "learning for the whole server, starting with 1 messages learned so far"
+    );
+    assert_eq!(command(&bot, "b/gen server").await, "hello there");
+
+    // and again once a channel is rebuilt
+    admin_command(&bot, "b/listen filter deny hello").await;
+    assert_eq!(command(&bot, "b/gen server").await, "hello there");
+    admin_command(&bot, "b/listen rebuild").await;
+    assert_eq!(command(&bot, "b/gen server").await, "");
+    admin_command(&bot, "b/listen filter allow hello").await;
+    admin_command(&bot, "b/listen rebuild").await;
+    assert_eq!(command(&bot, "b/gen server").await, "hello there");
+
+    // or something is cleared
+    admin_command(&bot, "b/listen clear confirm").await;
+    assert_eq!(command(&bot, "b/gen server").await, "");
+}
+
+#[tokio::test]
+async fn slow_commands_are_deferred() {
+    let bot = bot();
+    let admin = |content| MockHandler::new(content).level(Level::Admin);
+    assert_eq!(
+        run(&bot, admin("b/listen server")).await.deferred(),
+        Some(true)
+    );
+    assert_eq!(
+        run(&bot, admin("b/listen rebuild")).await.deferred(),
+        Some(true)
+    );
+    assert_eq!(
+        run(&bot, MockHandler::new("b/privacy forget"))
+            .await
+            .deferred(),
+        Some(false)
+    );
+    assert_eq!(run(&bot, admin("b/listen prob")).await.deferred(), None);
+    assert_eq!(run(&bot, MockHandler::new("b/gen")).await.deferred(), None);
+    // not before it's known to run
+    let denied = run(&bot, MockHandler::new("b/listen server")).await;
+    assert_eq!(denied.deferred(), None);
+    assert_eq!(denied.reply(), NOT_ENOUGH_PERMS);
+}
+
+#[tokio::test]
+async fn listen_unknown_subcommand() {
+    let bot = bot();
+    assert!(admin_command(&bot, "b/listen nope")
+        .await
+        .ends_with("`nope` isn't a command."));
+}
+
+#[tokio::test]
+async fn privacy() {
+    let bot = bot();
+    assert!(command(&bot, "b/privacy")
+        .await
+        .starts_with("control what the bot learns from you"));
+    listen_to(&bot, &[]).await;
+    assert!(command(&bot, "b/privacy optout")
+        .await
+        .starts_with("Fine, I won't listen to you anymore."));
+    run(&bot, MockHandler::new("secret")).await;
+    assert_eq!(command(&bot, "b/gen").await, "");
+    assert_eq!(
+        command(&bot, "b/privacy optin").await,
+        "I'll listen to you again."
+    );
+    run(&bot, MockHandler::new("not a secret")).await;
+    assert_eq!(command(&bot, "b/gen").await, "not a secret");
+    assert!(command(&bot, "b/privacy nope")
+        .await
+        .ends_with("`nope` isn't a command."));
+}
+
+#[tokio::test]
+async fn admin_replies_are_ephemeral() {
+    let bot = bot();
+    let toggled = run(&bot, MockHandler::new("b/listen").level(Level::Moderator)).await;
+    assert!(toggled.sent()[0].ephemeral);
+    let denied = run(&bot, MockHandler::new("b/listen prob 50")).await;
+    assert_eq!(denied.reply(), NOT_ENOUGH_PERMS);
+    assert!(denied.sent()[0].ephemeral);
+    // looking at a setting anyone can see isn't an admin command
+    let prob = run(
+        &bot,
+        MockHandler::new("b/listen prob").level(Level::Moderator),
+    )
+    .await;
+    assert!(!prob.sent()[0].ephemeral);
+    assert!(!run(&bot, MockHandler::new("b/gen")).await.sent()[0].ephemeral);
+}
+
+#[tokio::test]
+async fn run_command_without_a_message() {
+    let bot = bot();
+    listen_to(&bot, &["some words to learn"]).await;
+    let handler = MockHandler::new("");
+    bot.run_command(&handler, "gen", &["seed", "1"])
+        .await
+        .expect("mock handler can't fail");
+    assert_eq!(handler.reply(), command(&bot, "b/gen seed 1").await);
+}
+
+#[tokio::test]
+async fn levels() {
+    let bot = bot();
+    let as_level = |level| MockHandler::new("b/set insult").level(level);
+    assert_eq!(
+        run(&bot, as_level(Level::User)).await.reply(),
+        NOT_ENOUGH_PERMS
+    );
+    for level in [Level::Moderator, Level::Admin, Level::Owner] {
+        assert_ne!(run(&bot, as_level(level)).await.reply(), NOT_ENOUGH_PERMS);
+    }
+}
+
+#[tokio::test]
+async fn perms_grant_and_revoke() {
+    let bot = bot();
+    let grant = MockHandler::new("b/perms grant <@someone>").level(Level::Moderator);
+    assert_eq!(run(&bot, grant).await.reply(), NOT_ENOUGH_PERMS);
+    assert_eq!(
+        admin_command(&bot, "b/perms grant <@someone>").await,
+        "`someone` is a bot admin now"
+    );
+    let someone = || MockHandler::new("b/perms grantrole <@&mods>").author("someone");
+    assert_eq!(
+        run(&bot, someone()).await.reply(),
+        "members of `mods` are bot admins now"
+    );
+    let member = MockHandler::new("b/perms revoke someone").role("mods");
+    assert_eq!(
+        run(&bot, member).await.reply(),
+        "`someone` isn't a bot admin anymore"
+    );
+    assert_eq!(run(&bot, someone()).await.reply(), NOT_ENOUGH_PERMS);
+
+    let show = command(&bot, "b/perms").await;
+    assert!(show.contains("bot admins: none"), "{}", show);
+    assert!(show.contains("admin roles: `mods`"), "{}", show);
+
+    // grants are per guild
+    let elsewhere = MockHandler::new("b/set insult")
+        .role("mods")
+        .guild(Some("other"));
+    assert_eq!(run(&bot, elsewhere).await.reply(), NOT_ENOUGH_PERMS);
+}
+
+#[tokio::test]
+async fn perms_level() {
+    let bot = bot();
+    assert_eq!(
+        admin_command(&bot, "b/perms level admin generate seed").await,
+        "`gen seed` needs admin now"
+    );
+    assert_eq!(command(&bot, "b/gen seed 1").await, NOT_ENOUGH_PERMS);
+    assert_ne!(command(&bot, "b/gen").await, NOT_ENOUGH_PERMS);
+
+    // looking at a setting stays open to everyone
+    admin_command(&bot, "b/listen").await;
+    admin_command(&bot, "b/perms level user listen").await;
+    assert!(command(&bot, "b/listen order 2")
+        .await
+        .starts_with("Set order to 2"));
+    admin_command(&bot, "b/perms level admin listen prob").await;
+    assert_eq!(command(&bot, "b/listen prob").await, "Probability is 5%");
+    let moderator = MockHandler::new("b/listen prob 10").level(Level::Moderator);
+    assert_eq!(run(&bot, moderator).await.reply(), NOT_ENOUGH_PERMS);
+
+    assert_eq!(
+        admin_command(&bot, "b/perms reset gen seed").await,
+        "`gen seed` needs what it used to again"
+    );
+    assert_ne!(command(&bot, "b/gen seed 1").await, NOT_ENOUGH_PERMS);
+    assert_eq!(
+        admin_command(&bot, "b/perms level owner perms").await,
+        "nice try"
+    );
+    assert!(admin_command(&bot, "b/perms level boss gen")
+        .await
+        .starts_with("the level has to be one of"));
+    assert_eq!(
+        admin_command(&bot, "b/perms level user gen nope").await,
+        "I looked everywhere, there is no subcommand `nope` of `gen`. Stop making things up."
+    );
+}
+
+fn owned_bot(config: Config) -> Bot {
+    bot_with(Config {
+        owners: vec!["mock:owner".into()],
+        ..config
+    })
+}
+
+fn as_owner(content: &str) -> MockHandler {
+    MockHandler::new(content).author("owner")
+}
+
+#[tokio::test]
+async fn owner_commands_need_a_bot_owner() {
+    let bot = owned_bot(Config::default());
+    let server_owner = MockHandler::new("b/owner stats").level(Level::Owner);
+    assert_eq!(run(&bot, server_owner).await.reply(), NOT_ENOUGH_PERMS);
+    assert_eq!(
+        admin_command(&bot, "b/perms level user owner").await,
+        "nice try"
+    );
+
+    let stats = run(&bot, as_owner("b/owner stats")).await.sent();
+    assert!(stats[0].text.starts_with("channels: 0"), "{:?}", stats);
+    assert!(stats[0].ephemeral);
+    // and they're owner everywhere
+    let insult = run(&bot, as_owner("b/set insult")).await.reply();
+    assert_eq!(insult, "turned on insults");
+}
+
+#[tokio::test]
+async fn owner_stats() {
+    let bot = owned_bot(Config::default());
+    listen_to(&bot, &["hello there"]).await;
+    run(
+        &bot,
+        MockHandler::new("b/listen")
+            .level(Level::Admin)
+            .channel("dms")
+            .guild(None),
+    )
+    .await;
+    let stats = run(&bot, as_owner("b/owner stats")).await.reply();
+    assert!(
+        stats.contains("channels: 2 (2 learning, 2 speaking)"),
+        "{}",
+        stats
+    );
+    assert!(
+        stats.contains("servers: 1 (0 with a server chain)"),
+        "{}",
+        stats
+    );
+    assert!(stats.contains("- `mock`: 2 channels"), "{}", stats);
+    assert!(stats.contains("- `mock:guild`: 1 channels"), "{}", stats);
+}
+
+#[tokio::test]
+async fn owner_leave_and_broadcast() {
+    let bot = owned_bot(Config::default());
+    listen_to(&bot, &[]).await;
+    run(
+        &bot,
+        MockHandler::new("b/listen")
+            .level(Level::Admin)
+            .channel("other"),
+    )
+    .await;
+    run(
+        &bot,
+        MockHandler::new("b/listen speak")
+            .level(Level::Admin)
+            .channel("other"),
+    )
+    .await;
+    run(
+        &bot,
+        MockHandler::new("b/listen")
+            .level(Level::Admin)
+            .channel("third"),
+    )
+    .await;
+
+    let broadcast = run(&bot, as_owner("b/owner broadcast hear me")).await;
+    let mut sent_to = broadcast.sent_to();
+    sent_to.sort();
+    assert_eq!(
+        sent_to,
+        [
+            ("channel".to_owned(), "hear me".to_owned()),
+            ("third".to_owned(), "hear me".to_owned())
+        ]
+    );
+    assert_eq!(broadcast.reply(), "sent to 2 of 2 channels");
+
+    let leave = run(&bot, as_owner("b/owner leave somewhere")).await;
+    assert_eq!(leave.left(), ["somewhere"]);
+    assert_eq!(leave.reply(), "left `somewhere`");
+}
+
+#[tokio::test]
+async fn owner_reload() {
+    let bot = owned_bot(Config::default());
+    assert_eq!(
+        run(&bot, as_owner("b/owner reload")).await.reply(),
+        "I looked everywhere, there is no resources directory configured. Stop making things up."
+    );
+
+    let dir = tmp_dir().join("owner_reload");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("poems.txt"), "Built in.").unwrap();
+    let bot = owned_bot(Config {
+        resources_dir: Some(dir.clone()),
+        ..Config::default()
+    });
+    assert_eq!(command(&bot, "b/poem").await, "Built in.");
+    std::fs::write(dir.join("poems.txt"), "First poem.-Second poem.").unwrap();
+    assert!(run(&bot, as_owner("b/owner reload"))
+        .await
+        .reply()
+        .starts_with("reloaded 2 poems and "));
+    let poem = run(&bot, as_owner("b/poem")).await.reply();
+    assert!(poem == "First poem." || poem == "Second poem.", "{}", poem);
+}
+
+#[tokio::test]
+async fn owner_save() {
+    let bot = owned_bot(Config::default());
+    assert_eq!(run(&bot, as_owner("b/owner save")).await.reply(), "saved");
+}
+
+#[tokio::test]
+async fn unknown_command_insult_flow() {
+    let bot = bot();
+    let handler = run(&bot, MockHandler::new("b/nope")).await;
+    let insult = handler.sent().remove(0);
+    assert!(insult.text.ends_with("`nope` isn't a command."));
+    assert!(insult.reply);
+
+    // replying to anything else does nothing
+    let other = run(&bot, MockHandler::new("fuck you").referenced("other")).await;
+    assert!(other.sent().is_empty());
+
+    let response = run(&bot, MockHandler::new("fuck you").referenced(&insult.id)).await;
+    let sent = response.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].attachment.as_deref(), Some("umad.jpg"));
+}
+
+#[tokio::test]
+async fn errors_are_told_to_the_author() {
+    let bot = bot();
+    let invalid = run(&bot, MockHandler::new("b/gen seed x")).await;
+    assert_eq!(invalid.sent().len(), 1);
+    assert!(invalid.sent()[0].ephemeral);
+
+    let not_found = admin_command(&bot, "b/perms reset nope").await;
+    assert_eq!(
+        not_found,
+        BotError::<()>::NotFound("command called `nope`".into()).reply()
+    );
+    assert_eq!(
+        admin_command(&bot, "b/perms reset").await,
+        "put a command. Try again, dumb human."
+    );
+}
+
+fn limited_bot(user_rate_limit: Option<RateLimit>) -> Bot {
+    bot_with(Config {
+        user_rate_limit,
+        channel_rate_limit: None,
+        guild_rate_limit: None,
+        owners: vec!["mock:owner".into()],
+        ..Config::default()
+    })
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_limits() {
+    let bot = limited_bot(Some(RateLimit {
+        burst: 2,
+        per_minute: 30,
+    }));
+    assert!(command(&bot, "b/help").await.starts_with("commands are:"));
+    assert!(command(&bot, "b/help").await.starts_with("commands are:"));
+    let limited = run(&bot, MockHandler::new("b/help")).await;
+    assert_eq!(
+        limited.reply(),
+        "Slow down, human. I'll listen to you again in 2 seconds."
+    );
+    assert!(limited.sent()[0].ephemeral);
+    // told once, then ignored
+    assert!(run(&bot, MockHandler::new("b/help"))
+        .await
+        .sent()
+        .is_empty());
+
+    let other = run(&bot, MockHandler::new("b/help").author("other")).await;
+    assert!(other.reply().starts_with("commands are:"));
+    let owner = run(&bot, MockHandler::new("b/help").author("owner")).await;
+    assert!(owner.reply().starts_with("commands are:"));
+
+    tokio::time::advance(std::time::Duration::from_secs(2)).await;
+    assert!(command(&bot, "b/help").await.starts_with("commands are:"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn cooldowns() {
+    let bot = limited_bot(None);
+    assert_ne!(command(&bot, "b/fuckyou").await, NOT_ENOUGH_PERMS);
+    assert_eq!(
+        command(&bot, "b/fuckyou").await,
+        "Slow down, human. I'll listen to you again in 10 seconds."
+    );
+    assert!(command(&bot, "b/help").await.starts_with("commands are:"));
+    let other = run(&bot, MockHandler::new("b/fuckyou").author("other")).await;
+    assert_eq!(other.sent()[0].attachment.as_deref(), Some("umad.jpg"));
+
+    tokio::time::advance(std::time::Duration::from_secs(10)).await;
+    let again = run(&bot, MockHandler::new("b/fuckyou")).await;
+    assert_eq!(again.sent()[0].attachment.as_deref(), Some("umad.jpg"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn denied_commands_are_not_limited() {
+    let bot = limited_bot(Some(RateLimit {
+        burst: 1,
+        per_minute: 1,
+    }));
+    for _ in 0..3 {
+        assert_eq!(command(&bot, "b/listen").await, NOT_ENOUGH_PERMS);
+        assert_eq!(command(&bot, "b/owner stats").await, NOT_ENOUGH_PERMS);
+    }
+    assert!(command(&bot, "b/help").await.starts_with("commands are:"));
+    assert!(command(&bot, "b/help")
+        .await
+        .starts_with("Slow down, human."));
+}
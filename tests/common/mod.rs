@@ -0,0 +1,296 @@
+//! A [`Handler`] that records what the bot sends instead of sending it, and
+//! helpers to run commands through it.
+
+#![allow(dead_code)]
+
+use std::{
+    convert::Infallible,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use bernbot::{config::Config, perms::Level, storage::FileStorage, Bot, BotError, Handler};
+use parking_lot::Mutex;
+use smol_str::SmolStr;
+
+pub const BOT_ID: &str = "bot";
+
+/// A message sent through [`MockHandler::send_message`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sent {
+    pub id: SmolStr,
+    pub text: String,
+    pub attachment: Option<String>,
+    pub reply: bool,
+    pub ephemeral: bool,
+}
+
+/// A message from `user` in the `channel` of `guild`, who is just a user there.
+#[derive(Debug)]
+pub struct MockHandler {
+    content: String,
+    id: SmolStr,
+    author: SmolStr,
+    author_is_bot: bool,
+    level: Level,
+    roles: Vec<SmolStr>,
+    channel_id: SmolStr,
+    guild_id: Option<SmolStr>,
+    referenced_id: Option<SmolStr>,
+    sent: Mutex<Vec<Sent>>,
+    sent_to: Mutex<Vec<(String, String)>>,
+    left: Mutex<Vec<String>>,
+    deferred: Mutex<Option<bool>>,
+}
+
+impl MockHandler {
+    pub fn new(content: &str) -> Self {
+        Self {
+            content: content.to_owned(),
+            id: "message".into(),
+            author: "user".into(),
+            author_is_bot: false,
+            level: Level::User,
+            roles: Vec::new(),
+            channel_id: "channel".into(),
+            guild_id: Some("guild".into()),
+            referenced_id: None,
+            sent: Mutex::new(Vec::new()),
+            sent_to: Mutex::new(Vec::new()),
+            left: Mutex::new(Vec::new()),
+            deferred: Mutex::new(None),
+        }
+    }
+
+    pub fn author(mut self, author: &str) -> Self {
+        self.author = author.into();
+        self
+    }
+
+    pub fn author_is_bot(mut self, is_bot: bool) -> Self {
+        self.author_is_bot = is_bot;
+        self
+    }
+
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn role(mut self, role_id: &str) -> Self {
+        self.roles.push(role_id.into());
+        self
+    }
+
+    pub fn channel(mut self, channel_id: &str) -> Self {
+        self.channel_id = channel_id.into();
+        self
+    }
+
+    pub fn guild(mut self, guild_id: Option<&str>) -> Self {
+        self.guild_id = guild_id.map(Into::into);
+        self
+    }
+
+    pub fn referenced(mut self, message_id: &str) -> Self {
+        self.referenced_id = Some(message_id.into());
+        self
+    }
+
+    fn record(
+        &self,
+        text: &str,
+        attach: Option<(&str, Vec<u8>)>,
+        reply: bool,
+        ephemeral: bool,
+    ) -> SmolStr {
+        let mut sent = self.sent.lock();
+        let id = SmolStr::from(format!("sent-{}", sent.len()));
+        sent.push(Sent {
+            id: id.clone(),
+            text: text.to_owned(),
+            attachment: attach.map(|(name, _)| name.to_owned()),
+            reply,
+            ephemeral,
+        });
+        id
+    }
+
+    /// Everything sent so far, oldest first.
+    pub fn sent(&self) -> Vec<Sent> {
+        self.sent.lock().clone()
+    }
+
+    /// Channels and text of what was sent to other channels, oldest first.
+    pub fn sent_to(&self) -> Vec<(String, String)> {
+        self.sent_to.lock().clone()
+    }
+
+    /// Servers the bot was made to leave.
+    pub fn left(&self) -> Vec<String> {
+        self.left.lock().clone()
+    }
+
+    /// Whether the reply was deferred, and if so whether it was ephemeral.
+    pub fn deferred(&self) -> Option<bool> {
+        *self.deferred.lock()
+    }
+
+    /// Text of the only message sent, panicking if there wasn't exactly one.
+    pub fn reply(&self) -> String {
+        let sent = self.sent.lock();
+        assert_eq!(sent.len(), 1, "expected a single message, got {:?}", sent);
+        sent[0].text.clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler for MockHandler {
+    type Error = Infallible;
+
+    async fn send_message(
+        &self,
+        text: &str,
+        attach: Option<(&str, Vec<u8>)>,
+        reply: bool,
+    ) -> Result<SmolStr, BotError<Self::Error>> {
+        Ok(self.record(text, attach, reply, false))
+    }
+
+    async fn send_ephemeral(
+        &self,
+        text: &str,
+        attach: Option<(&str, Vec<u8>)>,
+    ) -> Result<SmolStr, BotError<Self::Error>> {
+        Ok(self.record(text, attach, true, true))
+    }
+
+    async fn author_level(&self) -> Result<Level, BotError<Self::Error>> {
+        Ok(self.level)
+    }
+
+    async fn author_roles(&self) -> Result<Vec<SmolStr>, BotError<Self::Error>> {
+        Ok(self.roles.clone())
+    }
+
+    async fn leave_guild(&self, guild_id: &str) -> Result<bool, BotError<Self::Error>> {
+        self.left.lock().push(guild_id.to_owned());
+        Ok(true)
+    }
+
+    async fn send_to(&self, channel_id: &str, text: &str) -> Result<bool, BotError<Self::Error>> {
+        self.sent_to
+            .lock()
+            .push((channel_id.to_owned(), text.to_owned()));
+        Ok(true)
+    }
+
+    async fn defer(&self, ephemeral: bool) -> Result<(), BotError<Self::Error>> {
+        *self.deferred.lock() = Some(ephemeral);
+        Ok(())
+    }
+
+    fn platform(&self) -> &str {
+        "mock"
+    }
+
+    fn bot_user_id(&self) -> &str {
+        BOT_ID
+    }
+
+    fn author_is_bot(&self) -> bool {
+        self.author_is_bot
+    }
+
+    fn referenced_id(&self) -> Option<&str> {
+        self.referenced_id.as_deref()
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn author(&self) -> &str {
+        &self.author
+    }
+
+    fn content(&self) -> &str {
+        &self.content
+    }
+
+    fn channel_id(&self) -> &str {
+        &self.channel_id
+    }
+
+    fn guild_id(&self) -> Option<&str> {
+        self.guild_id.as_deref()
+    }
+}
+
+/// A bot with a fixed seed and no rate limits, that would save to a file of
+/// its own next to the other test files if it was ever asked to.
+pub fn bot() -> Bot {
+    bot_with(Config {
+        user_rate_limit: None,
+        channel_rate_limit: None,
+        guild_rate_limit: None,
+        cooldowns: Default::default(),
+        ..Config::default()
+    })
+}
+
+/// Like [`bot`], with `config` instead of the default one.
+pub fn bot_with(config: Config) -> Bot {
+    let config = Config {
+        rng_seed: Some(0),
+        ..config
+    };
+    let storage = FileStorage::new(unique_tmp_path("data"));
+    Bot::new(Arc::new(storage), Arc::new(config))
+}
+
+/// Where tests can put files.
+pub fn tmp_dir() -> &'static std::path::Path {
+    std::path::Path::new(env!("CARGO_TARGET_TMPDIR"))
+}
+
+/// A path in [`tmp_dir`] starting with `name` that no other test uses, not
+/// even one running at the same time in another process.
+pub fn unique_tmp_path(name: &str) -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNT.fetch_add(1, Ordering::Relaxed);
+    tmp_dir().join(format!("{}-{}-{}", name, std::process::id(), n))
+}
+
+/// Runs `handler` through the bot and hands it back to look at what was sent.
+pub async fn run(bot: &Bot, handler: MockHandler) -> MockHandler {
+    bot.process_args(&handler)
+        .await
+        .expect("mock handler can't fail");
+    handler
+}
+
+/// Sends `content` as a user, returning the reply.
+pub async fn command(bot: &Bot, content: &str) -> String {
+    run(bot, MockHandler::new(content)).await.reply()
+}
+
+/// Sends `content` as an admin of the guild, returning the reply.
+pub async fn admin_command(bot: &Bot, content: &str) -> String {
+    run(bot, MockHandler::new(content).level(Level::Admin))
+        .await
+        .reply()
+}
+
+/// Marks the channel, stops the bot from talking back randomly and feeds it
+/// `messages`.
+pub async fn listen_to(bot: &Bot, messages: &[&str]) {
+    assert_eq!(admin_command(bot, "b/listen").await, "marked channel");
+    admin_command(bot, "b/listen prob 0").await;
+    for message in messages {
+        assert!(run(bot, MockHandler::new(message)).await.sent().is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+//! Saves through [`Bot::save`], with a storage that can be made to fail.
+
+mod common;
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use bernbot::{
+    config::Config,
+    storage::{Changes, Storage, StorageError},
+    Bot, BotData,
+};
+use common::{admin_command, run, MockHandler};
+use parking_lot::Mutex;
+
+/// Remembers what it was asked to save, and fails while `fail` is set.
+#[derive(Debug, Default)]
+struct FlakyStorage {
+    fail: AtomicBool,
+    saves: Mutex<Vec<Changes>>,
+}
+
+#[async_trait::async_trait]
+impl Storage for FlakyStorage {
+    async fn load(&self) -> Result<Option<BotData>, StorageError> {
+        Ok(None)
+    }
+
+    async fn save(&self, _data: &BotData, changes: &Changes) -> Result<(), StorageError> {
+        self.saves.lock().push(changes.clone());
+        if self.fail.load(Ordering::SeqCst) {
+            return Err(StorageError::Io(io::Error::other("disk is full")));
+        }
+        Ok(())
+    }
+}
+
+fn bot(storage: &Arc<FlakyStorage>) -> Bot {
+    let config = Config {
+        user_rate_limit: None,
+        channel_rate_limit: None,
+        guild_rate_limit: None,
+        cooldowns: Default::default(),
+        rng_seed: Some(0),
+        ..Config::default()
+    };
+    Bot::new(storage.clone(), Arc::new(config))
+}
+
+#[tokio::test]
+async fn failed_saves_are_retried() {
+    let storage = Arc::new(FlakyStorage::default());
+    let bot = bot(&storage);
+    admin_command(&bot, "b/set prefix !").await;
+
+    storage.fail.store(true, Ordering::SeqCst);
+    assert!(bot.save().await.is_err());
+    // changed while the save failed
+    admin_command(&bot, "!listen").await;
+    storage.fail.store(false, Ordering::SeqCst);
+    bot.save().await.unwrap();
+
+    let saves = storage.saves.lock();
+    assert_eq!(saves.len(), 2);
+    assert!(saves[0].prefix.contains("mock:guild"));
+    assert!(saves[0].mchain.is_empty());
+    assert!(saves[1].prefix.contains("mock:guild"));
+    assert!(saves[1].mchain.contains("mock:channel"));
+}
+
+#[tokio::test]
+async fn nothing_changed_saves_nothing() {
+    let storage = Arc::new(FlakyStorage::default());
+    let bot = bot(&storage);
+    bot.save().await.unwrap();
+    assert!(storage.saves.lock().is_empty());
+
+    admin_command(&bot, "b/set prefix !").await;
+    bot.save().await.unwrap();
+    bot.save().await.unwrap();
+    let saves = storage.saves.lock();
+    assert_eq!(saves.len(), 1);
+    assert!(saves[0].prefix.contains("mock:guild"));
+}
+
+#[tokio::test]
+async fn chatting_saves_nothing() {
+    let storage = Arc::new(FlakyStorage::default());
+    let bot = bot(&storage);
+    admin_command(&bot, "b/set insult").await;
+    bot.save().await.unwrap();
+    for _ in 0..5 {
+        run(&bot, MockHandler::new("hello")).await;
+    }
+    bot.save().await.unwrap();
+    let saves = storage.saves.lock();
+    assert_eq!(saves.len(), 1);
+    assert!(!saves[0].insult_data.is_empty());
+}